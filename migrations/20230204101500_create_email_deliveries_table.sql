@@ -0,0 +1,14 @@
+-- Add migration script here
+create table email_deliveries(
+    id uuid not null,
+    primary key (id),
+    recipient text not null,
+    template text not null,
+    subject text not null,
+    provider_message_id text null,
+    status text not null,
+    error text null,
+    attempted_at timestamptz not null
+);
+
+create index email_deliveries_recipient_idx on email_deliveries (lower(recipient));
@@ -0,0 +1,17 @@
+-- Add migration script here
+create table email_outbox(
+    id uuid not null,
+    primary key (id),
+    recipient text not null,
+    template text not null,
+    subject text not null,
+    html_body text not null,
+    text_body text not null,
+    created_at timestamptz not null,
+    attempts integer not null default 0,
+    next_attempt_at timestamptz not null,
+    last_error text null,
+    sent_at timestamptz null
+);
+
+create index email_outbox_pending_idx on email_outbox (next_attempt_at) where sent_at is null;
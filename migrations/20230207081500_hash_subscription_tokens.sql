@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Tokens are now stored as a keyed hash. Existing plaintext tokens are rehashed by
+-- the application on startup, which then clears the plaintext column.
+begin;
+    alter table subscription_tokens add column subscription_token_hash text null;
+    alter table subscription_tokens drop constraint subscription_tokens_pkey;
+    alter table subscription_tokens alter column subscription_token drop not null;
+    alter table subscription_tokens add constraint subscription_tokens_token_present
+        check (subscription_token is not null or subscription_token_hash is not null);
+    create unique index subscription_tokens_hash_idx on subscription_tokens (subscription_token_hash);
+commit;
@@ -0,0 +1,27 @@
+-- Add migration script here
+create table users(
+    user_id uuid not null,
+    primary key (user_id),
+    username text not null unique,
+    created_at timestamptz not null default now()
+);
+
+create table api_keys(
+    id uuid not null,
+    primary key (id),
+    user_id uuid not null references users(user_id),
+    name text not null,
+    key_prefix text not null,
+    key_hash text not null unique,
+    scopes text[] not null,
+    created_at timestamptz not null,
+    expires_at timestamptz null,
+    revoked_at timestamptz null,
+    last_used_at timestamptz null
+);
+
+create index api_keys_user_id_idx on api_keys (user_id);
+
+-- The first admin, who can be issued an API key with `zero2prod issue-api-key admin`
+insert into users (user_id, username)
+values ('ddf8994f-d522-4659-8d02-c1d479057be6', 'admin');
@@ -0,0 +1,5 @@
+-- Add migration script here
+alter table users add column role text not null default 'viewer'
+    check (role in ('viewer', 'editor', 'publisher', 'owner'));
+
+update users set role = 'owner' where username = 'admin';
@@ -0,0 +1,31 @@
+-- Add migration script here
+create table audit_events(
+    id bigserial not null,
+    primary key (id),
+    occurred_at timestamptz not null default now(),
+    actor_id uuid null,
+    api_key_id uuid null,
+    action text not null,
+    target text null,
+    request_id uuid null,
+    ip text null,
+    diff jsonb not null default '{}'
+);
+
+create index audit_events_actor_id_idx on audit_events (actor_id);
+create index audit_events_action_idx on audit_events (action);
+
+-- The audit log is append-only: events can be added but never changed or removed.
+create function reject_audit_event_changes() returns trigger as $$
+begin
+    raise exception 'audit_events is append-only';
+end;
+$$ language plpgsql;
+
+create trigger audit_events_are_immutable
+    before update or delete on audit_events
+    for each row execute function reject_audit_event_changes();
+
+create trigger audit_events_cannot_be_truncated
+    before truncate on audit_events
+    for each statement execute function reject_audit_event_changes();
@@ -0,0 +1,5 @@
+-- Add migration script here
+create type subscription_status as enum ('pending_confirmation', 'confirmed', 'unsubscribed');
+
+alter table subscriptions
+    alter column status type subscription_status using status::subscription_status;
@@ -0,0 +1,2 @@
+-- The site a signup was submitted from, as sent in the `Origin` header.
+alter table subscriptions add column signup_origin text null;
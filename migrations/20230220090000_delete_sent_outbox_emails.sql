@@ -0,0 +1,5 @@
+-- Sent emails are recorded in email_deliveries, so the outbox only keeps pending ones.
+delete from email_outbox where sent_at is not null;
+drop index email_outbox_pending_idx;
+alter table email_outbox drop column sent_at;
+create index email_outbox_pending_idx on email_outbox (next_attempt_at);
@@ -0,0 +1,8 @@
+-- Confirmation emails are rendered when they are sent, so the outbox keeps the
+-- subscriber they are for instead of bodies carrying a confirmation link.
+alter table email_outbox add column subscriber_id uuid null references subscriptions(id) on delete cascade;
+update email_outbox set subscriber_id = subscriptions.id
+    from subscriptions where subscriptions.email = email_outbox.recipient;
+delete from email_outbox where subscriber_id is null;
+alter table email_outbox alter column subscriber_id set not null;
+alter table email_outbox drop column html_body, drop column text_body;
@@ -0,0 +1,73 @@
+use serde_json::Value;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+pub enum AuditAction {
+    AccessDenied,
+    ApiKeyCreated,
+    ApiKeyRevoked,
+    LogFilterChanged,
+    NewsletterPublished,
+    SubscribersListed,
+    UserCreated,
+    UserRoleChanged,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::AccessDenied => "access.denied",
+            AuditAction::ApiKeyCreated => "api_key.created",
+            AuditAction::ApiKeyRevoked => "api_key.revoked",
+            AuditAction::LogFilterChanged => "log_filter.changed",
+            AuditAction::NewsletterPublished => "newsletter.published",
+            AuditAction::SubscribersListed => "subscribers.listed",
+            AuditAction::UserCreated => "user.created",
+            AuditAction::UserRoleChanged => "user.role_changed",
+        }
+    }
+}
+
+/// Who made a request and where it came from, captured when the caller is authorized.
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    pub actor_id: Uuid,
+    pub api_key_id: Uuid,
+    pub request_id: Option<Uuid>,
+    pub ip: Option<String>,
+}
+
+/// `diff` describes what changed, e.g. `{"role": {"from": "viewer", "to": "editor"}}`.
+#[tracing::instrument(
+    name = "Record an audit event",
+    skip(executor, context, diff),
+    fields(action = action.as_str(), actor_id = %context.actor_id)
+)]
+pub async fn record_audit_event(
+    executor: impl PgExecutor<'_>,
+    context: &AuditContext,
+    action: AuditAction,
+    target: Option<&str>,
+    diff: Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events (actor_id, api_key_id, action, target, request_id, ip, diff)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        context.actor_id,
+        context.api_key_id,
+        action.as_str(),
+        target,
+        context.request_id,
+        context.ip,
+        diff
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::pin::Pin;
+
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use sqlx::PgPool;
+use tracing_actix_web::RequestId;
+
+use super::{validate_api_key, ApiKeyOwner, RequiredPermission};
+use crate::audit::{record_audit_event, AuditAction, AuditContext};
+use crate::startup::HmacSecret;
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingCredentials,
+    InvalidCredentials,
+    Forbidden,
+    Unexpected(String),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::MissingCredentials => write!(f, "Missing bearer API key"),
+            AuthError::InvalidCredentials => write!(f, "Unknown, expired or revoked API key"),
+            AuthError::Forbidden => write!(f, "The caller lacks the required permission"),
+            AuthError::Unexpected(e) => write!(f, "Failed to authenticate the request: {}", e),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::MissingCredentials | AuthError::InvalidCredentials => {
+                StatusCode::UNAUTHORIZED
+            }
+            AuthError::Forbidden => StatusCode::FORBIDDEN,
+            AuthError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.finish()
+    }
+}
+
+/// Authenticates the request from its `Authorization: Bearer <api key>` header.
+impl FromRequest for ApiKeyOwner {
+    type Error = AuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let key = bearer_token(req.headers());
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let hmac_secret = req.app_data::<web::Data<HmacSecret>>().cloned();
+
+        Box::pin(async move {
+            let key = key.ok_or(AuthError::MissingCredentials)?;
+            let (pool, hmac_secret) = match (pool, hmac_secret) {
+                (Some(pool), Some(hmac_secret)) => (pool, hmac_secret),
+                _ => {
+                    return Err(AuthError::Unexpected(
+                        "the application state is not configured".into(),
+                    ))
+                }
+            };
+
+            match validate_api_key(&pool, &hmac_secret.0, &key).await {
+                Ok(Some(owner)) => Ok(owner),
+                Ok(None) => {
+                    tracing::warn!("Rejecting an unknown, expired or revoked API key");
+                    Err(AuthError::InvalidCredentials)
+                }
+                Err(e) => Err(AuthError::Unexpected(e.to_string())),
+            }
+        })
+    }
+}
+
+/// An authenticated caller whose role and API key both grant `P`'s permission.
+/// Taking it as a handler argument is all it takes to guard a route.
+pub struct Authorized<P> {
+    caller: ApiKeyOwner,
+    audit_context: AuditContext,
+    permission: PhantomData<P>,
+}
+
+impl<P> Authorized<P> {
+    pub fn audit_context(&self) -> &AuditContext {
+        &self.audit_context
+    }
+}
+
+impl<P> Deref for Authorized<P> {
+    type Target = ApiKeyOwner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.caller
+    }
+}
+
+impl<P: RequiredPermission> FromRequest for Authorized<P> {
+    type Error = AuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let caller = ApiKeyOwner::from_request(req, payload);
+        let path = req.path().to_string();
+        let request_id = req.extensions().get::<RequestId>().map(|id| **id);
+        // Forwarding headers are set by the client unless a proxy overwrites them,
+        // so only the address of the connection itself is recorded.
+        let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+
+        Box::pin(async move {
+            let caller = caller.await?;
+            let audit_context = AuditContext {
+                actor_id: caller.user_id,
+                api_key_id: caller.key_id,
+                request_id,
+                ip,
+            };
+            let permission = P::PERMISSION;
+            if !caller.role.permits(permission) || !caller.has_scope(permission.scope()) {
+                tracing::warn!(
+                    user_id = %caller.user_id,
+                    role = caller.role.as_str(),
+                    permission = permission.as_str(),
+                    "Access denied to {}",
+                    path
+                );
+                if let Some(pool) = pool {
+                    let diff = serde_json::json!({
+                        "permission": permission.as_str(),
+                        "role": caller.role.as_str(),
+                    });
+                    let recorded = record_audit_event(
+                        pool.get_ref(),
+                        &audit_context,
+                        AuditAction::AccessDenied,
+                        Some(&path),
+                        diff,
+                    )
+                    .await;
+                    if let Err(e) = recorded {
+                        tracing::error!("Failed to record the denied request: {:?}", e);
+                    }
+                }
+                return Err(AuthError::Forbidden);
+            }
+            Ok(Authorized {
+                caller,
+                audit_context,
+                permission: PhantomData,
+            })
+        })
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+
+    use super::bearer_token;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn a_bearer_token_is_extracted() {
+        assert_eq!(
+            bearer_token(&headers("Bearer zp_abc")).as_deref(),
+            Some("zp_abc")
+        );
+    }
+
+    #[test]
+    fn other_schemes_and_empty_tokens_are_ignored() {
+        assert_eq!(bearer_token(&headers("Basic dXNlcjpwYXNz")), None);
+        assert_eq!(bearer_token(&headers("Bearer ")), None);
+        assert_eq!(bearer_token(&HeaderMap::new()), None);
+    }
+}
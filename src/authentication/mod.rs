@@ -0,0 +1,7 @@
+mod api_key;
+mod extractor;
+mod roles;
+
+pub use api_key::*;
+pub use extractor::*;
+pub use roles::*;
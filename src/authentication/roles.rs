@@ -0,0 +1,173 @@
+use super::Scope;
+
+/// Roles are cumulative: each one is granted everything the roles before it are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Editor,
+    Publisher,
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Viewer, Role::Editor, Role::Publisher, Role::Owner];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Publisher => "publisher",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn permits(&self, permission: Permission) -> bool {
+        *self >= permission.minimum_role()
+    }
+}
+
+impl TryFrom<&str> for Role {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == value)
+            .ok_or_else(|| format!("{} is not a supported role", value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewDeliveries,
+    ManageApiKeys,
+    ViewSubscribers,
+    PublishNewsletters,
+    ManageUsers,
+    ViewAuditLog,
+    ManageLogging,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ViewDeliveries => "view_deliveries",
+            Permission::ManageApiKeys => "manage_api_keys",
+            Permission::ViewSubscribers => "view_subscribers",
+            Permission::PublishNewsletters => "publish_newsletters",
+            Permission::ManageUsers => "manage_users",
+            Permission::ViewAuditLog => "view_audit_log",
+            Permission::ManageLogging => "manage_logging",
+        }
+    }
+
+    pub fn minimum_role(&self) -> Role {
+        match self {
+            Permission::ManageApiKeys => Role::Viewer,
+            // Delivery records name their recipients, so they are as sensitive
+            // as the subscriber list itself.
+            Permission::ViewDeliveries | Permission::ViewSubscribers => Role::Editor,
+            Permission::PublishNewsletters => Role::Publisher,
+            Permission::ManageUsers | Permission::ViewAuditLog | Permission::ManageLogging => {
+                Role::Owner
+            }
+        }
+    }
+
+    /// The scope an API key needs on top of its user's role.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Permission::ViewDeliveries | Permission::ViewSubscribers => Scope::SubscribersRead,
+            Permission::ManageApiKeys => Scope::ApiKeysManage,
+            Permission::PublishNewsletters => Scope::NewslettersPublish,
+            Permission::ManageUsers => Scope::UsersManage,
+            Permission::ViewAuditLog => Scope::AuditRead,
+            Permission::ManageLogging => Scope::LoggingManage,
+        }
+    }
+}
+
+/// Names the permission an `Authorized` extractor checks for.
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct ViewDeliveries;
+pub struct ManageApiKeys;
+pub struct ViewSubscribers;
+pub struct PublishNewsletters;
+pub struct ManageUsers;
+pub struct ViewAuditLog;
+pub struct ManageLogging;
+
+impl RequiredPermission for ViewDeliveries {
+    const PERMISSION: Permission = Permission::ViewDeliveries;
+}
+
+impl RequiredPermission for ManageApiKeys {
+    const PERMISSION: Permission = Permission::ManageApiKeys;
+}
+
+impl RequiredPermission for ViewSubscribers {
+    const PERMISSION: Permission = Permission::ViewSubscribers;
+}
+
+impl RequiredPermission for PublishNewsletters {
+    const PERMISSION: Permission = Permission::PublishNewsletters;
+}
+
+impl RequiredPermission for ManageUsers {
+    const PERMISSION: Permission = Permission::ManageUsers;
+}
+
+impl RequiredPermission for ViewAuditLog {
+    const PERMISSION: Permission = Permission::ViewAuditLog;
+}
+
+impl RequiredPermission for ManageLogging {
+    const PERMISSION: Permission = Permission::ManageLogging;
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::*;
+
+    #[test]
+    fn a_viewer_cannot_see_subscribers_or_publish() {
+        assert!(Role::Viewer.permits(Permission::ManageApiKeys));
+        assert!(!Role::Viewer.permits(Permission::ViewSubscribers));
+        assert!(!Role::Viewer.permits(Permission::PublishNewsletters));
+    }
+
+    #[test]
+    fn an_editor_can_see_subscribers_but_not_publish() {
+        assert!(Role::Editor.permits(Permission::ViewSubscribers));
+        assert!(Role::Editor.permits(Permission::ViewDeliveries));
+        assert!(!Role::Editor.permits(Permission::PublishNewsletters));
+    }
+
+    #[test]
+    fn deliveries_are_withheld_from_viewers_like_subscribers() {
+        assert!(!Role::Viewer.permits(Permission::ViewDeliveries));
+        assert_eq!(
+            Permission::ViewDeliveries.minimum_role(),
+            Permission::ViewSubscribers.minimum_role()
+        );
+    }
+
+    #[test]
+    fn only_owners_can_manage_users() {
+        assert!(!Role::Publisher.permits(Permission::ManageUsers));
+        assert!(Role::Owner.permits(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in Role::ALL {
+            assert_eq!(assert_ok!(Role::try_from(role.as_str())), role);
+        }
+        assert_err!(Role::try_from("admin"));
+    }
+}
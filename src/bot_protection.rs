@@ -0,0 +1,396 @@
+use std::fmt;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::configuration::{BotProtectionSettings, ChallengeProvider};
+
+/// The response `StubChallenge` accepts.
+pub const STUB_PASSING_RESPONSE: &str = "pass";
+
+/// When the signup form was handed out, signed so it cannot be backdated:
+/// `<unix seconds>.<signature>`.
+pub struct FormToken(String);
+
+impl FormToken {
+    pub fn issue(secret: &Secret<String>, started_at: DateTime<Utc>) -> FormToken {
+        let started_at = started_at.timestamp();
+        let signature = hex::encode(mac(secret, started_at).finalize().into_bytes());
+        FormToken(format!("{}.{}", started_at, signature))
+    }
+
+    /// `None` unless the token was issued with `secret`.
+    pub fn started_at(secret: &Secret<String>, token: &str) -> Option<DateTime<Utc>> {
+        let (started_at, signature) = token.split_once('.')?;
+        let started_at: i64 = started_at.parse().ok()?;
+        let signature = hex::decode(signature).ok()?;
+        mac(secret, started_at).verify_slice(&signature).ok()?;
+        Utc.timestamp_opt(started_at, 0).single()
+    }
+}
+
+impl AsRef<str> for FormToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn mac(secret: &Secret<String>, started_at: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(format!("signup-form.{}", started_at).as_bytes());
+    mac
+}
+
+/// Checks the response a challenge widget produced once the visitor solved it.
+#[async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    /// `Ok(false)` for responses the provider rejects, `Err` when it could not be asked.
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// hCaptcha and Turnstile share the same siteverify API.
+pub struct SiteVerifyChallenge {
+    http_client: Client,
+    verify_url: String,
+    secret_key: Secret<String>,
+}
+
+#[derive(Serialize)]
+struct SiteVerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl SiteVerifyChallenge {
+    pub fn new(verify_url: String, secret_key: Secret<String>) -> SiteVerifyChallenge {
+        let http_client = Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("Unable to build the siteverify client");
+        Self {
+            http_client,
+            verify_url,
+            secret_key,
+        }
+    }
+}
+
+#[async_trait]
+impl ChallengeVerifier for SiteVerifyChallenge {
+    #[tracing::instrument(name = "Verify a challenge response", skip_all)]
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error> {
+        let verdict: SiteVerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&SiteVerifyRequest {
+                secret: self.secret_key.expose_secret(),
+                response,
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(verdict.success)
+    }
+}
+
+/// Passes `STUB_PASSING_RESPONSE` and nothing else, without calling out.
+pub struct StubChallenge;
+
+#[async_trait]
+impl ChallengeVerifier for StubChallenge {
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error> {
+        Ok(response == STUB_PASSING_RESPONSE)
+    }
+}
+
+/// What a signup carries besides the subscriber's details.
+pub struct SignupSignals<'a> {
+    pub honeypot: &'a str,
+    pub form_token: Option<&'a str>,
+    pub challenge_response: Option<&'a str>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Suspicion {
+    HoneypotFilled,
+    MissingFormToken,
+    SubmittedTooFast,
+    FormExpired,
+    ChallengeFailed,
+}
+
+impl fmt::Display for Suspicion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Suspicion::HoneypotFilled => "the honeypot field was filled in",
+            Suspicion::MissingFormToken => "the form token is missing or invalid",
+            Suspicion::SubmittedTooFast => "the form was submitted too fast",
+            Suspicion::FormExpired => "the form token has expired",
+            Suspicion::ChallengeFailed => "the challenge was not solved",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// The challenge the signup widget renders, if any.
+#[derive(Serialize)]
+pub struct ChallengeWidget {
+    pub provider: ChallengeProvider,
+    pub site_key: String,
+}
+
+pub struct BotProtection {
+    settings: BotProtectionSettings,
+    challenge: Option<Box<dyn ChallengeVerifier>>,
+}
+
+impl BotProtection {
+    pub fn new(settings: BotProtectionSettings) -> BotProtection {
+        let challenge = settings.challenge.as_ref().map(|c| c.verifier());
+        Self {
+            settings,
+            challenge,
+        }
+    }
+
+    pub fn challenge_widget(&self) -> Option<ChallengeWidget> {
+        self.settings
+            .challenge
+            .as_ref()
+            .map(|challenge| ChallengeWidget {
+                provider: challenge.provider,
+                site_key: challenge.site_key.clone(),
+            })
+    }
+
+    /// The cheap checks run first, so bots that fail them never reach the challenge provider.
+    pub async fn check(
+        &self,
+        secret: &Secret<String>,
+        signals: &SignupSignals<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Suspicion>, anyhow::Error> {
+        if !signals.honeypot.is_empty() {
+            return Ok(Some(Suspicion::HoneypotFilled));
+        }
+        let started_at = match signals
+            .form_token
+            .and_then(|token| FormToken::started_at(secret, token))
+        {
+            Some(started_at) => started_at,
+            None => return Ok(Some(Suspicion::MissingFormToken)),
+        };
+        if now - started_at < self.settings.min_fill_time() {
+            return Ok(Some(Suspicion::SubmittedTooFast));
+        }
+        if now - started_at > self.settings.max_form_age() {
+            return Ok(Some(Suspicion::FormExpired));
+        }
+        if let Some(challenge) = &self.challenge {
+            let solved = match signals.challenge_response {
+                Some(response) if !response.is_empty() => challenge.verify(response).await?,
+                _ => false,
+            };
+            if !solved {
+                return Ok(Some(Suspicion::ChallengeFailed));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use claim::{assert_err, assert_ok};
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::configuration::ChallengeSettings;
+
+    fn secret() -> Secret<String> {
+        Secret::new("hmac-secret".to_string())
+    }
+
+    fn bot_protection(challenge: Option<ChallengeSettings>) -> BotProtection {
+        BotProtection::new(BotProtectionSettings {
+            min_fill_seconds: 3,
+            max_form_age_hours: 1,
+            challenge,
+        })
+    }
+
+    fn stub_challenge() -> Option<ChallengeSettings> {
+        Some(ChallengeSettings {
+            provider: ChallengeProvider::Stub,
+            site_key: "site-key".to_string(),
+            secret_key: Secret::new("secret-key".to_string()),
+            verify_url: None,
+        })
+    }
+
+    fn token_started(ago: Duration) -> FormToken {
+        FormToken::issue(&secret(), Utc::now() - ago)
+    }
+
+    async fn check(
+        protection: &BotProtection,
+        honeypot: &str,
+        form_token: Option<&str>,
+        challenge_response: Option<&str>,
+    ) -> Option<Suspicion> {
+        let signals = SignupSignals {
+            honeypot,
+            form_token,
+            challenge_response,
+        };
+        protection
+            .check(&secret(), &signals, Utc::now())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn a_form_token_reveals_when_it_was_issued() {
+        let started_at = Utc.timestamp_opt(1_676_000_000, 0).unwrap();
+        let token = FormToken::issue(&secret(), started_at);
+
+        assert_eq!(
+            FormToken::started_at(&secret(), token.as_ref()),
+            Some(started_at)
+        );
+    }
+
+    #[test]
+    fn backdated_or_foreign_form_tokens_are_rejected() {
+        let token = FormToken::issue(&secret(), Utc::now());
+        let (_, signature) = token.as_ref().split_once('.').unwrap();
+        let backdated = format!("{}.{}", Utc::now().timestamp() - 60, signature);
+        let foreign = FormToken::issue(&Secret::new("another-secret".to_string()), Utc::now());
+
+        assert_eq!(FormToken::started_at(&secret(), &backdated), None);
+        assert_eq!(FormToken::started_at(&secret(), foreign.as_ref()), None);
+        assert_eq!(FormToken::started_at(&secret(), "not-a-token"), None);
+    }
+
+    #[actix_web::test]
+    async fn a_form_filled_in_at_human_speed_passes() {
+        let protection = bot_protection(None);
+        let token = token_started(Duration::seconds(10));
+
+        assert_eq!(
+            check(&protection, "", Some(token.as_ref()), None).await,
+            None
+        );
+    }
+
+    #[actix_web::test]
+    async fn each_bot_signal_is_caught() {
+        let protection = bot_protection(None);
+        let recent = token_started(Duration::zero());
+        let stale = token_started(Duration::hours(2));
+        let good = token_started(Duration::seconds(10));
+
+        assert_eq!(
+            check(
+                &protection,
+                "http://spam.example.com",
+                Some(good.as_ref()),
+                None
+            )
+            .await,
+            Some(Suspicion::HoneypotFilled)
+        );
+        assert_eq!(
+            check(&protection, "", None, None).await,
+            Some(Suspicion::MissingFormToken)
+        );
+        assert_eq!(
+            check(&protection, "", Some(recent.as_ref()), None).await,
+            Some(Suspicion::SubmittedTooFast)
+        );
+        assert_eq!(
+            check(&protection, "", Some(stale.as_ref()), None).await,
+            Some(Suspicion::FormExpired)
+        );
+    }
+
+    #[actix_web::test]
+    async fn a_configured_challenge_must_be_solved() {
+        let protection = bot_protection(stub_challenge());
+        let token = token_started(Duration::seconds(10));
+
+        assert_eq!(
+            check(&protection, "", Some(token.as_ref()), None).await,
+            Some(Suspicion::ChallengeFailed)
+        );
+        assert_eq!(
+            check(&protection, "", Some(token.as_ref()), Some("wrong")).await,
+            Some(Suspicion::ChallengeFailed)
+        );
+        assert_eq!(
+            check(
+                &protection,
+                "",
+                Some(token.as_ref()),
+                Some(STUB_PASSING_RESPONSE)
+            )
+            .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn siteverify_sends_the_secret_and_response_and_reads_the_verdict() {
+        let server = MockServer::start().await;
+        let verifier =
+            SiteVerifyChallenge::new(server.uri(), Secret::new("secret-key".to_string()));
+        Mock::given(method("POST"))
+            .and(body_string_contains("secret=secret-key"))
+            .and(body_string_contains("response=solved"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("response=unsolved"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false,
+                "error-codes": ["invalid-input-response"]
+            })))
+            .mount(&server)
+            .await;
+
+        assert!(assert_ok!(verifier.verify("solved").await));
+        assert!(!assert_ok!(verifier.verify("unsolved").await));
+    }
+
+    #[tokio::test]
+    async fn siteverify_failures_are_errors() {
+        let server = MockServer::start().await;
+        let verifier =
+            SiteVerifyChallenge::new(server.uri(), Secret::new("secret-key".to_string()));
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        assert_err!(verifier.verify("solved").await);
+    }
+}
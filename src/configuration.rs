@@ -1,21 +1,39 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use crate::bot_protection::{ChallengeVerifier, SiteVerifyChallenge, StubChallenge};
 use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
+use actix_web::cookie::{Cookie, SameSite};
 use config::Config;
 use config::ConfigError;
+use config::Value;
+use config::ValueKind;
+use reqwest::Url;
 use secrecy::ExposeSecret;
 use secrecy::Secret;
-use serde::Deserialize;
-use serde_aux::prelude::deserialize_number_from_string;
+use serde::{Deserialize, Serialize};
+use serde_aux::prelude::{deserialize_number_from_string, deserialize_option_number_from_string};
 use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::PgSslMode;
+use sqlx::ConnectOptions;
+use tracing::log::LevelFilter;
+use tracing_subscriber::EnvFilter;
 
+/// `email_client`, `confirmation_links`, `telemetry.log.filter` and the TLS
+/// certificate are reloaded while the application is running, see
+/// `SettingsReloader`. Everything else is only read at startup.
 #[derive(Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: AppSettings,
     pub email_client: EmailClientSettings,
+    pub confirmation_links: ConfirmationLinkSettings,
+    pub security: SecuritySettings,
+    pub bot_protection: BotProtectionSettings,
+    pub telemetry: TelemetrySettings,
 }
 
 #[derive(Deserialize, Clone)]
@@ -26,24 +44,101 @@ pub struct DatabaseSettings {
     pub port: u16,
     pub host: String,
     pub database_name: String,
+    pub ssl_mode: DatabaseSslMode,
+    /// CA certificate to verify the server with, instead of the system's trust store.
+    pub ssl_root_cert: Option<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    pub acquire_timeout_milliseconds: u64,
+    /// Idle connections above `min_connections` are closed after this long.
+    pub idle_timeout_seconds: u64,
+    /// Connections are recycled after this long, even if they are busy.
+    pub max_lifetime_seconds: u64,
+    /// The level every executed statement is logged at.
+    pub statement_log_level: StatementLogLevel,
+    /// Apply pending migrations when the application starts. Otherwise it
+    /// refuses to start until `--migrate-only` has brought the schema up to date.
+    pub migrate_on_startup: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum DatabaseSslMode {
+    Disable,
+    /// Use TLS if the server supports it.
+    Prefer,
+    Require,
+    /// Like `require`, and also verify the server certificate.
+    VerifyCa,
+    /// Like `verify-ca`, and also check the certificate matches `host`.
+    VerifyFull,
+}
+
+impl From<DatabaseSslMode> for PgSslMode {
+    fn from(mode: DatabaseSslMode) -> Self {
+        match mode {
+            DatabaseSslMode::Disable => PgSslMode::Disable,
+            DatabaseSslMode::Prefer => PgSslMode::Prefer,
+            DatabaseSslMode::Require => PgSslMode::Require,
+            DatabaseSslMode::VerifyCa => PgSslMode::VerifyCa,
+            DatabaseSslMode::VerifyFull => PgSslMode::VerifyFull,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementLogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<StatementLogLevel> for LevelFilter {
+    fn from(level: StatementLogLevel) -> Self {
+        match level {
+            StatementLogLevel::Off => LevelFilter::Off,
+            StatementLogLevel::Error => LevelFilter::Error,
+            StatementLogLevel::Warn => LevelFilter::Warn,
+            StatementLogLevel::Info => LevelFilter::Info,
+            StatementLogLevel::Debug => LevelFilter::Debug,
+            StatementLogLevel::Trace => LevelFilter::Trace,
+        }
+    }
 }
 
 impl DatabaseSettings {
     pub fn without_db(&self) -> PgConnectOptions {
-        PgConnectOptions::new()
+        let options = PgConnectOptions::new()
             .host(&self.host)
             .port(self.port)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
+            .ssl_mode(self.ssl_mode.into());
+        match &self.ssl_root_cert {
+            Some(ssl_root_cert) => options.ssl_root_cert(ssl_root_cert),
+            None => options,
+        }
     }
 
     pub fn with_db(&self) -> PgConnectOptions {
-        self.without_db().database(&self.database_name)
+        let mut options = self.without_db().database(&self.database_name);
+        options.log_statements(self.statement_log_level.into());
+        options
     }
 
     pub fn get_connection_pool(self) -> sqlx::PgPool {
         PgPoolOptions::new()
-            .acquire_timeout(Duration::from_secs(2))
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(Duration::from_millis(self.acquire_timeout_milliseconds))
+            .idle_timeout(Duration::from_secs(self.idle_timeout_seconds))
+            .max_lifetime(Duration::from_secs(self.max_lifetime_seconds))
             .connect_lazy_with(self.with_db())
     }
 }
@@ -54,6 +149,137 @@ pub struct AppSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    /// Serve HTTPS, with HTTP/2, instead of plain HTTP.
+    pub tls: Option<TlsSettings>,
+    /// Sites allowed to call `/subscribe` from the browser, such as the signup
+    /// widget, e.g. `https://www.example.com`.
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TlsSettings {
+    /// PEM encoded, leaf certificate first.
+    pub certificate_path: String,
+    /// PEM encoded PKCS#1, PKCS#8 or SEC1 key.
+    pub private_key_path: String,
+    /// Also listen for plain HTTP on this port, redirecting every request to `base_url`.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub http_redirect_port: Option<u16>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SecuritySettings {
+    /// `max-age` of the `Strict-Transport-Security` header. 0 leaves the header
+    /// out, which is what plain HTTP deployments need.
+    pub hsts_max_age_seconds: u64,
+    pub content_security_policy: String,
+    pub cookies: CookieSettings,
+}
+
+/// Applies to every cookie the application sets. They are always `HttpOnly`.
+#[derive(Deserialize, Clone)]
+pub struct CookieSettings {
+    /// Only send cookies over HTTPS.
+    pub secure: bool,
+    pub same_site: CookieSameSite,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    /// Browsers only accept it on `secure` cookies.
+    None,
+}
+
+impl From<CookieSameSite> for SameSite {
+    fn from(same_site: CookieSameSite) -> Self {
+        match same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+}
+
+/// Signups that look automated get a normal response, but nothing is stored or sent.
+#[derive(Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    /// Signups submitted sooner after the form was loaded are taken for bots.
+    pub min_fill_seconds: i64,
+    /// Form tokens older than this are refused, so a bot cannot keep reusing one.
+    pub max_form_age_hours: i64,
+    /// Also require solving a challenge. Off when absent.
+    pub challenge: Option<ChallengeSettings>,
+}
+
+impl BotProtectionSettings {
+    pub fn min_fill_time(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.min_fill_seconds)
+    }
+
+    pub fn max_form_age(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.max_form_age_hours)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ChallengeSettings {
+    pub provider: ChallengeProvider,
+    /// Public, the signup widget renders the challenge with it.
+    pub site_key: String,
+    pub secret_key: Secret<String>,
+    /// Defaults to the provider's siteverify endpoint.
+    pub verify_url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeProvider {
+    Hcaptcha,
+    Turnstile,
+    /// Passes the response `pass` without calling out, for tests.
+    Stub,
+}
+
+impl ChallengeSettings {
+    pub fn verifier(&self) -> Box<dyn ChallengeVerifier> {
+        match self.provider {
+            ChallengeProvider::Stub => Box::new(StubChallenge),
+            provider => Box::new(SiteVerifyChallenge::new(
+                self.verify_url
+                    .clone()
+                    .unwrap_or_else(|| provider.verify_url().to_string()),
+                self.secret_key.clone(),
+            )),
+        }
+    }
+}
+
+impl ChallengeProvider {
+    fn verify_url(&self) -> &'static str {
+        match self {
+            ChallengeProvider::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+            ChallengeProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+            ChallengeProvider::Stub => "",
+        }
+    }
+}
+
+impl CookieSettings {
+    pub fn cookie(&self, name: &'static str, value: String) -> Cookie<'static> {
+        Cookie::build(name, value)
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site.into())
+            .finish()
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -72,55 +298,630 @@ impl EmailClientSettings {
         Duration::from_millis(self.timeout_milliseconds)
     }
 
-    pub fn email_client(self) -> EmailClient {
-        let sender_email = self
-            .sender()
-            .expect("Invalid email id configured for sender");
+    pub fn email_client(self) -> Result<EmailClient, String> {
+        let sender_email = self.sender()?;
 
         let timeout = self.timeout();
-        let email_client = EmailClient::new(
+        Ok(EmailClient::new(
             self.base_url,
             self.authorization_token,
             sender_email,
             timeout,
-        );
+        ))
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TelemetrySettings {
+    /// Spans are only logged unless an OTLP collector is configured.
+    pub otlp: Option<OtlpSettings>,
+    #[serde(default)]
+    pub pii: PiiPolicy,
+    /// Keys the hashes logged in place of personal data with the `hash` policy.
+    /// It must differ from `application.hmac_secret`, so anyone who can read the
+    /// logs and learns it still cannot forge tokens or signatures.
+    pub redaction_key: Secret<String>,
+    #[serde(default)]
+    pub log: LogSettings,
+}
 
-        email_client
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct LogSettings {
+    pub format: LogFormat,
+    /// `EnvFilter` directives, e.g. `info,sqlx=warn`. `RUST_LOG` takes precedence.
+    pub filter: String,
+    pub sink: LogSink,
+    /// Only used with the `file` sink.
+    pub file: LogFileSettings,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Bunyan,
+            filter: "info".to_string(),
+            sink: LogSink::Stdout,
+            file: LogFileSettings::default(),
+        }
     }
 }
 
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line.
+    Bunyan,
+    /// Multi-line and coloured, for reading in a terminal.
+    Pretty,
+    /// One line per event.
+    Compact,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    Stdout,
+    File,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct LogFileSettings {
+    pub directory: String,
+    /// Files are named `<prefix>.<period>`, e.g. `zero2prod.2023-02-20` when rotated daily.
+    pub prefix: String,
+    pub rotation: LogRotation,
+}
+
+impl Default for LogFileSettings {
+    fn default() -> Self {
+        Self {
+            directory: "logs".to_string(),
+            prefix: "zero2prod".to_string(),
+            rotation: LogRotation::Daily,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+/// How subscriber details and secrets show up in spans and logs.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiPolicy {
+    /// Keep just enough to tell values apart at a glance, e.g. `u***@example.com`.
+    #[default]
+    Mask,
+    /// Replace values with a short keyed hash.
+    Hash,
+    /// Log values as they are. Only meant for local development.
+    Raw,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct OtlpSettings {
+    /// The collector's OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    pub endpoint: String,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmationLinkMode {
+    /// A random token is stored for every new subscriber and looked up on confirmation.
+    Token,
+    /// The link carries the subscriber ID, list and expiry, signed with `signing_keys`.
+    Signed,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ConfirmationLinkSettings {
+    pub mode: ConfirmationLinkMode,
+    pub list: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub validity_hours: i64,
+    /// The first key signs new links. Keep a rotated-out key listed for at least
+    /// `validity_hours` so the links it signed can still be confirmed.
+    pub signing_keys: Vec<SigningKey>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SigningKey {
+    pub id: String,
+    pub secret: Secret<String>,
+}
+
+impl ConfirmationLinkSettings {
+    pub fn validity(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.validity_hours)
+    }
+
+    pub fn active_key(&self) -> Option<&SigningKey> {
+        self.signing_keys.first()
+    }
+
+    pub fn key(&self, id: &str) -> Option<&SigningKey> {
+        self.signing_keys.iter().find(|key| key.id == id)
+    }
+}
+
+impl Settings {
+    /// Checks what deserializing cannot, reporting every problem rather than the first one.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        let mut urls = vec![
+            ("application.base_url", &self.application.base_url),
+            ("email_client.base_url", &self.email_client.base_url),
+        ];
+        if let Some(otlp) = &self.telemetry.otlp {
+            urls.push(("telemetry.otlp.endpoint", &otlp.endpoint));
+        }
+        if let Some(verify_url) = self
+            .bot_protection
+            .challenge
+            .as_ref()
+            .and_then(|challenge| challenge.verify_url.as_ref())
+        {
+            urls.push(("bot_protection.challenge.verify_url", verify_url));
+        }
+        for (key, url) in urls {
+            if let Err(e) = Url::parse(url) {
+                problems.push(format!("{} is not a valid URL: {}", key, e));
+            }
+        }
+        if let Err(e) = EnvFilter::try_new(&self.telemetry.log.filter) {
+            problems.push(format!("telemetry.log.filter is not a valid filter: {}", e));
+        }
+        if let Err(e) = self.email_client.sender() {
+            problems.push(format!("email_client.sender_email: {}", e));
+        }
+        for (key, value) in [
+            (
+                "email_client.timeout_milliseconds",
+                self.email_client.timeout_milliseconds,
+            ),
+            (
+                "database.acquire_timeout_milliseconds",
+                self.database.acquire_timeout_milliseconds,
+            ),
+            (
+                "database.max_connections",
+                self.database.max_connections.into(),
+            ),
+        ] {
+            if value == 0 {
+                problems.push(format!("{} must be greater than zero", key));
+            }
+        }
+        if let Some(tls) = &self.application.tls {
+            if !self.application.base_url.starts_with("https://") {
+                problems.push("application.base_url must use https with tls".to_string());
+            }
+            // Port 0 picks a free port for each listener.
+            if self.application.port != 0 && tls.http_redirect_port == Some(self.application.port) {
+                problems.push(
+                    "application.tls.http_redirect_port must differ from application.port"
+                        .to_string(),
+                );
+            }
+        }
+        for origin in &self.application.cors_allowed_origins {
+            if !is_origin(origin) {
+                problems.push(format!(
+                    "application.cors_allowed_origins: {} is not an origin, e.g. https://www.example.com",
+                    origin
+                ));
+            }
+        }
+        if self.security.cookies.same_site == CookieSameSite::None && !self.security.cookies.secure
+        {
+            problems
+                .push("security.cookies.same_site can only be none for secure cookies".to_string());
+        }
+        if self.database.min_connections > self.database.max_connections {
+            problems.push(
+                "database.min_connections must not exceed database.max_connections".to_string(),
+            );
+        }
+        if self.bot_protection.min_fill_seconds < 0 {
+            problems.push("bot_protection.min_fill_seconds must not be negative".to_string());
+        }
+        if self.bot_protection.max_form_age_hours <= 0 {
+            problems
+                .push("bot_protection.max_form_age_hours must be greater than zero".to_string());
+        }
+        if self.confirmation_links.validity_hours <= 0 {
+            problems
+                .push("confirmation_links.validity_hours must be greater than zero".to_string());
+        }
+        if self.confirmation_links.mode == ConfirmationLinkMode::Signed
+            && self.confirmation_links.signing_keys.is_empty()
+        {
+            problems.push(
+                "confirmation_links.signing_keys must not be empty for signed links".to_string(),
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// A scheme, host and optional port, exactly as browsers send it in the `Origin` header.
+fn is_origin(s: &str) -> bool {
+    Url::parse(s)
+        .is_ok_and(|url| url.origin().is_tuple() && url.origin().ascii_serialization() == s)
+}
+
+/// Layers `base.yaml`, the file for `APP_ENVIRONMENT`, the optional file at
+/// `APP_CONFIG_OVERLAY` and finally `APP__` environment variables.
 pub fn get_configuration() -> Result<Settings, ConfigError> {
+    let (config_dir, environment, overlay) = configuration_sources()?;
+    load_configuration(&config_dir, environment, overlay.as_deref())
+}
+
+/// The effective configuration, one `key = value` line per setting along with
+/// the layer it came from. Secrets are redacted.
+pub fn describe_configuration() -> Result<String, ConfigError> {
+    let (config_dir, environment, overlay) = configuration_sources()?;
+    let layers = layers(&config_dir, environment, overlay.as_deref())?;
+    let base_path = std::env::current_dir().expect("Unable to resolve base path");
+    describe(&layers, &base_path)
+}
+
+/// The files `get_configuration` layers, lowest precedence first.
+pub fn configuration_files() -> Result<Vec<PathBuf>, ConfigError> {
+    let (config_dir, environment, overlay) = configuration_sources()?;
+    Ok(layer_files(&config_dir, environment, overlay.as_deref()))
+}
+
+fn configuration_sources() -> Result<(PathBuf, Environment, Option<PathBuf>), ConfigError> {
     let base_path = std::env::current_dir().expect("Unable to resolve base path");
     let config_dir = base_path.join("configuration");
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or("local".to_string())
         .try_into()
-        .expect("Unable to parse APP_ENVIRONMENT");
+        .map_err(|e| ConfigError::Message(format!("Unable to parse APP_ENVIRONMENT: {}", e)))?;
+    let overlay = std::env::var_os("APP_CONFIG_OVERLAY").map(PathBuf::from);
+    Ok((config_dir, environment, overlay))
+}
+
+fn load_configuration(
+    config_dir: &Path,
+    environment: Environment,
+    overlay: Option<&Path>,
+) -> Result<Settings, ConfigError> {
+    let layers = layers(config_dir, environment, overlay)?;
+    let config = read_secret_files(merge(&layers)?)?;
+
+    if let Environment::Production = environment {
+        let (_, base) = &layers[0];
+        reject_default_secrets(&config, base)?;
+        reject_shared_redaction_key(&config)?;
+    }
+
+    let settings = config.try_deserialize::<Settings>()?;
+    settings.validate().map_err(|problems| {
+        ConfigError::Message(format!(
+            "Invalid configuration:\n- {}",
+            problems.join("\n- ")
+        ))
+    })?;
+    Ok(settings)
+}
+
+/// Every source of settings and its name, from the lowest precedence to the highest.
+fn layers(
+    config_dir: &Path,
+    environment: Environment,
+    overlay: Option<&Path>,
+) -> Result<Vec<(String, Config)>, ConfigError> {
+    let mut layers = Vec::new();
+    for file in layer_files(config_dir, environment, overlay) {
+        let layer = Config::builder()
+            .add_source(config::File::from(file.as_path()))
+            .build()?;
+        layers.push((file.display().to_string(), layer));
+    }
+    layers.extend(environment_variable_layers(None)?);
+    Ok(layers)
+}
 
+/// `APP__DATABASE__PASSWORD_FILE` sets `database.password_file`. Nesting is marked
+/// with `__`, so the single underscores in setting names are kept.
+///
+/// Variables in the deprecated form, such as `APP__DATABASE_HOST`, are still read
+/// with every `_` marking nesting, as they were before. They come first, so the
+/// current form wins when both set a value. `source` stands in for the process
+/// environment in tests.
+fn environment_variable_layers(
+    source: Option<config::Map<String, String>>,
+) -> Result<Vec<(String, Config)>, ConfigError> {
+    let variables = source.unwrap_or_else(app_variables);
+    let (deprecated, current): (config::Map<_, _>, config::Map<_, _>) = variables
+        .into_iter()
+        .partition(|(name, _)| is_deprecated_variable(name));
+
+    let layer = |variables, separator| {
+        Config::builder()
+            .add_source(
+                config::Environment::with_prefix("APP")
+                    .prefix_separator("__")
+                    .separator(separator)
+                    .source(Some(variables)),
+            )
+            .build()
+    };
+    Ok(vec![
+        (
+            "APP__ environment variables (deprecated form)".to_string(),
+            layer(deprecated, "_")?,
+        ),
+        (
+            "APP__ environment variables".to_string(),
+            layer(current, "__")?,
+        ),
+    ])
+}
+
+/// The `APP__` variables in the deprecated single-underscore form. They will
+/// stop being read in a future release.
+pub fn deprecated_environment_variables() -> Vec<String> {
+    let mut names: Vec<String> = app_variables()
+        .into_keys()
+        .filter(|name| is_deprecated_variable(name))
+        .collect();
+    names.sort();
+    names
+}
+
+fn app_variables() -> config::Map<String, String> {
+    std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| name.starts_with("APP__"))
+        .collect()
+}
+
+/// Every setting is nested, so a variable without a `__` after the prefix can
+/// only be in the old form.
+fn is_deprecated_variable(name: &str) -> bool {
+    name.strip_prefix("APP__")
+        .is_some_and(|setting| !setting.contains("__"))
+}
+
+fn layer_files(
+    config_dir: &Path,
+    environment: Environment,
+    overlay: Option<&Path>,
+) -> Vec<PathBuf> {
     let environment_filename = format!("{}.yaml", environment.as_str());
+    let mut files = vec![
+        config_dir.join("base.yaml"),
+        config_dir.join(environment_filename),
+    ];
+    files.extend(overlay.map(Path::to_path_buf));
+    files
+}
+
+fn merge(layers: &[(String, Config)]) -> Result<Config, ConfigError> {
+    layers
+        .iter()
+        .fold(Config::builder(), |builder, (_, layer)| {
+            builder.add_source(layer.clone())
+        })
+        .build()
+}
 
-    let config = Config::builder()
-        .add_source(config::File::from(config_dir.join("base.yaml")))
-        .add_source(config::File::from(config_dir.join(environment_filename)))
-        .add_source(
-            config::Environment::with_prefix("APP")
-                .prefix_separator("__")
-                .separator("_"),
-        )
-        .build()?;
+fn describe(layers: &[(String, Config)], base_path: &Path) -> Result<String, ConfigError> {
+    let merged = merge(layers)?;
+    // A setting comes from the last layer that sets it, or from the file it names.
+    let mut origins = HashMap::new();
+    for (name, layer) in layers {
+        let mut settings = Vec::new();
+        collect_settings("", &layer.cache, &mut settings);
+        for (key, _) in settings {
+            origins.insert(key, name.clone());
+        }
+    }
+    let mut references = Vec::new();
+    find_file_references("", &merged.cache, &mut references);
+    origins.extend(references);
 
-    config.try_deserialize::<Settings>()
+    let config = read_secret_files(merged)?;
+    let secrets: HashSet<String> = secrets(&config).into_iter().map(|(key, _)| key).collect();
+    let mut settings = Vec::new();
+    collect_settings("", &config.cache, &mut settings);
+
+    let mut description = String::new();
+    for (key, value) in settings {
+        let shown = if secrets.contains(&key) {
+            "[redacted]".to_string()
+        } else {
+            match &value.kind {
+                ValueKind::String(s) => format!("{:?}", s),
+                _ => value.to_string(),
+            }
+        };
+        let origin = match origins.get(&key) {
+            Some(origin) => {
+                let origin = Path::new(origin);
+                origin
+                    .strip_prefix(base_path)
+                    .unwrap_or(origin)
+                    .display()
+                    .to_string()
+            }
+            None => "default".to_string(),
+        };
+        description.push_str(&format!("{} = {}  # {}\n", key, shown, origin));
+    }
+    Ok(description)
 }
 
+/// Flattens `value` into `(key, value)` pairs, sorted by key.
+fn collect_settings<'a>(prefix: &str, value: &'a Value, settings: &mut Vec<(String, &'a Value)>) {
+    match &value.kind {
+        ValueKind::Table(table) => {
+            let mut names: Vec<&String> = table.keys().collect();
+            names.sort();
+            for name in names {
+                let key = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", prefix, name)
+                };
+                collect_settings(&key, &table[name], settings);
+            }
+        }
+        ValueKind::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                collect_settings(&format!("{}[{}]", prefix, index), value, settings);
+            }
+        }
+        _ => settings.push((prefix.to_string(), value)),
+    }
+}
+
+/// Any setting can be read from a file, such as a mounted Docker or Kubernetes
+/// secret, by giving its path as `<name>_file` instead of setting `<name>`.
+fn read_secret_files(config: Config) -> Result<Config, ConfigError> {
+    let mut references = Vec::new();
+    find_file_references("", &config.cache, &mut references);
+    if references.is_empty() {
+        return Ok(config);
+    }
+
+    let mut builder = Config::builder().add_source(config);
+    for (key, path) in references {
+        let contents = std::fs::read_to_string(&path).map_err(|e| {
+            ConfigError::Message(format!("Unable to read {} from {}: {}", key, path, e))
+        })?;
+        builder = builder.set_override(key, contents.trim_end_matches(['\r', '\n']))?;
+    }
+    builder.build()
+}
+
+/// Collects `(key, path)` for every `<key>_file` setting.
+fn find_file_references(prefix: &str, value: &Value, references: &mut Vec<(String, String)>) {
+    match &value.kind {
+        ValueKind::Table(table) => {
+            let key = |name: &str| {
+                if prefix.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}.{}", prefix, name)
+                }
+            };
+            for (name, value) in table {
+                match (name.strip_suffix("_file"), &value.kind) {
+                    (Some(name), ValueKind::String(path)) => {
+                        references.push((key(name), path.clone()))
+                    }
+                    _ => find_file_references(&key(name), value, references),
+                }
+            }
+        }
+        ValueKind::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                find_file_references(&format!("{}[{}]", prefix, index), value, references);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The secrets in `base.yaml` are public, so production must override every one of them.
+fn reject_default_secrets(config: &Config, base: &Config) -> Result<(), ConfigError> {
+    let defaults: HashSet<String> = secrets(base)
+        .into_iter()
+        .map(|(_, secret)| secret)
+        .collect();
+    let unchanged: Vec<String> = secrets(config)
+        .into_iter()
+        .filter(|(_, secret)| defaults.contains(secret))
+        .map(|(key, _)| key)
+        .collect();
+    if unchanged.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::Message(format!(
+            "Refusing to start in production with the default value from base.yaml for {}",
+            unchanged.join(", ")
+        )))
+    }
+}
+
+fn reject_shared_redaction_key(config: &Config) -> Result<(), ConfigError> {
+    match (
+        config.get_string("telemetry.redaction_key"),
+        config.get_string("application.hmac_secret"),
+    ) {
+        (Ok(redaction_key), Ok(hmac_secret)) if redaction_key == hmac_secret => {
+            Err(ConfigError::Message(
+                "Refusing to start in production with telemetry.redaction_key equal to application.hmac_secret"
+                    .to_string(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn secrets(config: &Config) -> Vec<(String, String)> {
+    let mut keys: Vec<String> = [
+        "application.hmac_secret",
+        "database.password",
+        "email_client.authorization_token",
+        "bot_protection.challenge.secret_key",
+        "telemetry.redaction_key",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    if let Ok(signing_keys) = config.get_array("confirmation_links.signing_keys") {
+        keys.extend(
+            (0..signing_keys.len())
+                .map(|index| format!("confirmation_links.signing_keys[{}].secret", index)),
+        );
+    }
+    keys.into_iter()
+        .filter_map(|key| {
+            let secret = config.get_string(&key).ok()?;
+            Some((key, secret))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Environment {
     Local,
+    Test,
+    Staging,
     Production,
 }
 
 impl Environment {
+    pub const ALL: [Environment; 4] = [
+        Environment::Local,
+        Environment::Test,
+        Environment::Staging,
+        Environment::Production,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Environment::Local => "local",
+            Environment::Test => "test",
+            Environment::Staging => "staging",
             Environment::Production => "production",
         }
     }
@@ -130,10 +931,415 @@ impl TryFrom<String> for Environment {
     type Error = String;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.to_lowercase().as_str() {
-            "local" => Ok(Environment::Local),
-            "production" => Ok(Environment::Production),
-            x => Err(format!("{} is not a supported environment", x)),
+        let value = value.to_lowercase();
+        Environment::ALL
+            .into_iter()
+            .find(|environment| environment.as_str() == value)
+            .ok_or_else(|| format!("{} is not a supported environment", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use config::FileFormat;
+
+    use super::*;
+
+    const BASE: &str = r#"
+application:
+  hmac_secret: "base-hmac-secret"
+database:
+  password: "base-password"
+email_client:
+  authorization_token: "base-token"
+confirmation_links:
+  signing_keys:
+    - id: "2023-02"
+      secret: "base-signing-secret"
+telemetry:
+  redaction_key: "base-redaction-key"
+"#;
+
+    fn config(layers: &[&str]) -> Config {
+        layers
+            .iter()
+            .fold(Config::builder(), |builder, layer| {
+                builder.add_source(config::File::from_str(layer, FileFormat::Yaml))
+            })
+            .build()
+            .unwrap()
+    }
+
+    fn secret_file(contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn database_tls_and_pool_settings_are_read() {
+        let database = r#"
+username: "app"
+password: "password"
+port: "5432"
+host: "db.internal"
+database_name: "newsletter"
+ssl_mode: verify-full
+ssl_root_cert: "/etc/ssl/db-ca.pem"
+max_connections: "20"
+min_connections: 2
+acquire_timeout_milliseconds: 2000
+idle_timeout_seconds: 600
+max_lifetime_seconds: 1800
+statement_log_level: debug
+migrate_on_startup: false
+"#;
+
+        let settings: DatabaseSettings = assert_ok!(config(&[database]).try_deserialize());
+
+        assert_eq!(settings.ssl_mode, DatabaseSslMode::VerifyFull);
+        assert_eq!(
+            settings.ssl_root_cert.as_deref(),
+            Some("/etc/ssl/db-ca.pem")
+        );
+        assert_eq!(settings.max_connections, 20);
+        assert_eq!(
+            LevelFilter::from(settings.statement_log_level),
+            LevelFilter::Debug
+        );
+    }
+
+    fn local_settings() -> Settings {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration");
+        assert_ok!(load_configuration(&config_dir, Environment::Local, None))
+    }
+
+    #[test]
+    fn every_environment_has_a_name_and_a_configuration_file() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration");
+        for environment in Environment::ALL {
+            let parsed = Environment::try_from(environment.as_str().to_uppercase());
+            assert_eq!(assert_ok!(parsed), environment);
+            assert!(config_dir
+                .join(format!("{}.yaml", environment.as_str()))
+                .exists());
         }
+        assert_err!(Environment::try_from("qa".to_string()));
+    }
+
+    #[test]
+    fn an_overlay_file_takes_precedence_over_the_environment_file() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration");
+        // The file format is inferred from the extension.
+        let overlay = std::env::temp_dir().join(format!("{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&overlay, "application:\n  port: 9123\n").unwrap();
+
+        let settings = assert_ok!(load_configuration(
+            &config_dir,
+            Environment::Local,
+            Some(&overlay)
+        ));
+
+        assert_eq!(settings.application.port, 9123);
+        std::fs::remove_file(overlay).unwrap();
+    }
+
+    #[test]
+    fn the_local_configuration_is_valid() {
+        assert_ok!(local_settings().validate());
+    }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let mut settings = local_settings();
+        settings.application.base_url = "not a url".to_string();
+        settings.email_client.sender_email = "not-an-email".to_string();
+        settings.email_client.timeout_milliseconds = 0;
+        settings.database.acquire_timeout_milliseconds = 0;
+
+        let problems = settings.validate().unwrap_err();
+
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].starts_with("application.base_url"));
+        assert!(problems[1].starts_with("email_client.sender_email"));
+        assert!(problems[2].starts_with("email_client.timeout_milliseconds"));
+        assert!(problems[3].starts_with("database.acquire_timeout_milliseconds"));
+    }
+
+    #[test]
+    fn tls_needs_an_https_base_url_and_a_separate_redirect_port() {
+        let mut settings = local_settings();
+        settings.application.tls = Some(TlsSettings {
+            certificate_path: "cert.pem".to_string(),
+            private_key_path: "key.pem".to_string(),
+            http_redirect_port: Some(settings.application.port),
+        });
+
+        let problems = settings.validate().unwrap_err();
+
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("application.base_url"));
+        assert!(problems[1].starts_with("application.tls.http_redirect_port"));
+    }
+
+    #[test]
+    fn the_log_filter_must_parse() {
+        let mut settings = local_settings();
+        settings.telemetry.log.filter = "zero2prod=loudest".to_string();
+
+        let problems = settings.validate().unwrap_err();
+
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("telemetry.log.filter"));
+    }
+
+    #[test]
+    fn cors_origins_must_be_bare_origins() {
+        let mut settings = local_settings();
+        settings.application.cors_allowed_origins = vec![
+            "https://www.example.com".to_string(),
+            "http://localhost:3000".to_string(),
+            "https://www.example.com/".to_string(),
+            "https://www.example.com/signup".to_string(),
+            "*".to_string(),
+        ];
+
+        let problems = settings.validate().unwrap_err();
+
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems
+            .iter()
+            .all(|p| p.starts_with("application.cors_allowed_origins")));
+    }
+
+    #[test]
+    fn the_description_annotates_values_and_redacts_secrets() {
+        let overlay = r#"
+application:
+  port: 8080
+"#;
+        let layer = |name: &str, yaml: &str| {
+            let config = Config::builder()
+                .add_source(config::File::from_str(yaml, FileFormat::Yaml))
+                .build()
+                .unwrap();
+            (name.to_string(), config)
+        };
+        let layers = vec![layer("base", BASE), layer("overlay", overlay)];
+
+        let description = describe(&layers, Path::new("/")).unwrap();
+
+        assert!(description.contains("application.port = 8080  # overlay"));
+        assert!(description.contains("database.password = [redacted]  # base"));
+        assert!(description.contains("confirmation_links.signing_keys[0].id = \"2023-02\""));
+        assert!(description.contains("confirmation_links.signing_keys[0].secret = [redacted]"));
+        assert!(!description.contains("base-password"));
+        assert!(!description.contains("base-signing-secret"));
+    }
+
+    #[test]
+    fn the_description_names_the_file_each_value_came_from() {
+        let config_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("configuration");
+        let layers = layers(&config_dir, Environment::Local, None).unwrap();
+
+        let description = describe(&layers, Path::new(env!("CARGO_MANIFEST_DIR"))).unwrap();
+
+        let line = |key: &str| {
+            description
+                .lines()
+                .find(|line| line.starts_with(&format!("{} = ", key)))
+                .unwrap()
+                .to_string()
+        };
+        assert!(line("application.host").ends_with("# configuration/local.yaml"));
+        assert!(line("database.port").ends_with("# configuration/base.yaml"));
+    }
+
+    #[test]
+    fn secrets_can_be_read_from_files() {
+        let password = secret_file("from-a-file\n");
+        let signing_key = secret_file("signing-key-from-a-file");
+        let overlay = format!(
+            "database:\n  password_file: {}\nconfirmation_links:\n  signing_keys:\n    - id: \"2023-03\"\n      secret_file: {}\n",
+            password.display(),
+            signing_key.display()
+        );
+
+        let config = assert_ok!(read_secret_files(config(&[BASE, &overlay])));
+
+        assert_eq!(
+            config.get_string("database.password").unwrap(),
+            "from-a-file"
+        );
+        assert_eq!(
+            config
+                .get_string("confirmation_links.signing_keys[0].secret")
+                .unwrap(),
+            "signing-key-from-a-file"
+        );
+        std::fs::remove_file(password).unwrap();
+        std::fs::remove_file(signing_key).unwrap();
+    }
+
+    fn environment_config(variables: config::Map<String, String>) -> Config {
+        let mut layers = vec![("base".to_string(), config(&[BASE]))];
+        layers.extend(environment_variable_layers(Some(variables)).unwrap());
+        merge(&layers).unwrap()
+    }
+
+    #[test]
+    fn deprecated_environment_variables_are_still_read() {
+        let variables = config::Map::from([(
+            "APP__DATABASE_PASSWORD".to_string(),
+            "from-a-deprecated-variable".to_string(),
+        )]);
+
+        let config = environment_config(variables);
+
+        assert_eq!(
+            config.get_string("database.password").unwrap(),
+            "from-a-deprecated-variable"
+        );
+    }
+
+    #[test]
+    fn the_current_form_wins_over_the_deprecated_one() {
+        let variables = config::Map::from([
+            (
+                "APP__DATABASE_PASSWORD".to_string(),
+                "from-a-deprecated-variable".to_string(),
+            ),
+            (
+                "APP__DATABASE__PASSWORD".to_string(),
+                "from-a-current-variable".to_string(),
+            ),
+        ]);
+
+        let config = environment_config(variables);
+
+        assert_eq!(
+            config.get_string("database.password").unwrap(),
+            "from-a-current-variable"
+        );
+    }
+
+    #[test]
+    fn only_variables_without_nesting_are_deprecated() {
+        assert!(is_deprecated_variable("APP__DATABASE_HOST"));
+        assert!(is_deprecated_variable("APP__PORT"));
+        assert!(!is_deprecated_variable("APP__DATABASE__HOST"));
+        assert!(!is_deprecated_variable("APP__EMAIL_CLIENT__BASE_URL"));
+        assert!(!is_deprecated_variable("APP_ENVIRONMENT"));
+        assert!(!is_deprecated_variable("APP_CONFIG_OVERLAY"));
+    }
+
+    #[test]
+    fn environment_variables_can_point_at_secret_files() {
+        let password = secret_file("from-an-env-file");
+        let variables = config::Map::from([
+            (
+                "APP__DATABASE__PASSWORD_FILE".to_string(),
+                password.display().to_string(),
+            ),
+            (
+                "APP__APPLICATION__HMAC_SECRET".to_string(),
+                "env-hmac-secret".to_string(),
+            ),
+        ]);
+        let config = environment_config(variables);
+
+        let config = assert_ok!(read_secret_files(config));
+
+        assert_eq!(
+            config.get_string("database.password").unwrap(),
+            "from-an-env-file"
+        );
+        assert_eq!(
+            config.get_string("application.hmac_secret").unwrap(),
+            "env-hmac-secret"
+        );
+        std::fs::remove_file(password).unwrap();
+    }
+
+    #[test]
+    fn a_missing_secret_file_is_an_error() {
+        let overlay = "database:\n  password_file: /does/not/exist\n";
+        assert_err!(read_secret_files(config(&[BASE, overlay])));
+    }
+
+    #[test]
+    fn default_secrets_are_rejected() {
+        let overlay = r#"
+database:
+  password: "a-real-password"
+"#;
+        let base = config(&[BASE]);
+
+        let error = reject_default_secrets(&config(&[BASE, overlay]), &base)
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("application.hmac_secret"));
+        assert!(error.contains("email_client.authorization_token"));
+        assert!(error.contains("confirmation_links.signing_keys[0].secret"));
+        assert!(error.contains("telemetry.redaction_key"));
+        assert!(!error.contains("database.password"));
+    }
+
+    #[test]
+    fn overridden_secrets_are_accepted() {
+        let overlay = r#"
+application:
+  hmac_secret: "real-hmac-secret"
+database:
+  password: "real-password"
+email_client:
+  authorization_token: "real-token"
+confirmation_links:
+  signing_keys:
+    - id: "2023-03"
+      secret: "real-signing-secret"
+telemetry:
+  redaction_key: "real-redaction-key"
+"#;
+        let base = config(&[BASE]);
+
+        assert_ok!(reject_default_secrets(&config(&[BASE, overlay]), &base));
+        assert_ok!(reject_shared_redaction_key(&config(&[BASE, overlay])));
+    }
+
+    #[test]
+    fn a_redaction_key_equal_to_the_hmac_secret_is_rejected() {
+        let overlay = r#"
+application:
+  hmac_secret: "real-hmac-secret"
+telemetry:
+  redaction_key: "real-hmac-secret"
+"#;
+
+        let error = reject_shared_redaction_key(&config(&[BASE, overlay]))
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("telemetry.redaction_key"));
+        assert!(!error.contains("real-hmac-secret"));
+    }
+
+    #[test]
+    fn the_redaction_key_can_be_read_from_a_file() {
+        let redaction_key = secret_file("redaction-key-from-a-file\n");
+        let overlay = format!(
+            "telemetry:\n  redaction_key_file: {}\n",
+            redaction_key.display()
+        );
+
+        let config = assert_ok!(read_secret_files(config(&[BASE, &overlay])));
+
+        assert_eq!(
+            config.get_string("telemetry.redaction_key").unwrap(),
+            "redaction-key-from-a-file"
+        );
+        std::fs::remove_file(redaction_key).unwrap();
     }
 }
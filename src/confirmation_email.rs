@@ -0,0 +1,182 @@
+//! The confirmation email is rendered by the outbox dispatcher when it is sent,
+//! not when the subscriber signs up, so the outbox never holds a working link.
+
+use reqwest::Url;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::configuration::{ConfirmationLinkMode, ConfirmationLinkSettings};
+use crate::domain::{SignedConfirmation, SubscriptionToken};
+use crate::redaction::redact_secret;
+use crate::reload::Reloadable;
+
+pub const CONFIRMATION_SUBJECT: &str = "Welcome !";
+
+/// Builds confirmation emails with whichever link settings are current when
+/// each one is sent.
+#[derive(Clone)]
+pub struct ConfirmationEmailRenderer {
+    base_url: String,
+    hmac_secret: Secret<String>,
+    confirmation_links: Reloadable<ConfirmationLinkSettings>,
+}
+
+pub struct ConfirmationEmail {
+    pub html_body: String,
+    pub text_body: String,
+    /// The keyed hash of the token in the link, when the link carries one. It
+    /// is stored once the email has been sent.
+    pub token_hash: Option<String>,
+}
+
+impl ConfirmationEmailRenderer {
+    pub fn new(
+        base_url: String,
+        hmac_secret: Secret<String>,
+        confirmation_links: Reloadable<ConfirmationLinkSettings>,
+    ) -> ConfirmationEmailRenderer {
+        Self {
+            base_url,
+            hmac_secret,
+            confirmation_links,
+        }
+    }
+
+    pub fn render(&self, subscriber_id: Uuid) -> Result<ConfirmationEmail, String> {
+        let settings = self.confirmation_links.current();
+        let (confirmation_link, token_hash) = match settings.mode {
+            ConfirmationLinkMode::Token => {
+                let subscription_token = SubscriptionToken::generate();
+                (
+                    token_confirmation_link(&self.base_url, &subscription_token),
+                    Some(subscription_token.hash(&self.hmac_secret)),
+                )
+            }
+            ConfirmationLinkMode::Signed => (
+                signed_confirmation_link(&self.base_url, subscriber_id, &settings)?,
+                None,
+            ),
+        };
+        tracing::debug!("Confirmation link: {}", redact_secret(&confirmation_link));
+
+        let html_body = format!(
+            "Welcome to our newsletter!<br/> \
+                Click <a href=\"{}\">here</a> to confirm the subscription.",
+            confirmation_link
+        );
+        let text_body = format!(
+            "Welcome to our newsletter! \n Visit {} to confirm your subscription.",
+            confirmation_link
+        );
+        Ok(ConfirmationEmail {
+            html_body,
+            text_body,
+            token_hash,
+        })
+    }
+}
+
+fn token_confirmation_link(base_url: &str, subscription_token: &SubscriptionToken) -> String {
+    format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url,
+        subscription_token.as_ref()
+    )
+}
+
+fn signed_confirmation_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    settings: &ConfirmationLinkSettings,
+) -> Result<String, String> {
+    let key = settings
+        .active_key()
+        .ok_or_else(|| "No signing key is configured for confirmation links".to_string())?;
+    let confirmation =
+        SignedConfirmation::new(subscriber_id, settings.list.clone(), settings.validity());
+
+    let confirmation_link = Url::parse_with_params(
+        &format!("{}/subscriptions/confirm", base_url),
+        &[
+            ("subscriber_id", confirmation.subscriber_id.to_string()),
+            ("list", confirmation.list.clone()),
+            ("expires", confirmation.expires_at.to_string()),
+            ("key_id", key.id.clone()),
+            ("signature", confirmation.signature(&key.secret)),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(confirmation_link.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::SigningKey;
+
+    fn renderer(mode: ConfirmationLinkMode) -> ConfirmationEmailRenderer {
+        ConfirmationEmailRenderer::new(
+            "http://127.0.0.1".to_string(),
+            Secret::new("hmac-secret".to_string()),
+            Reloadable::new(ConfirmationLinkSettings {
+                mode,
+                list: "newsletter".to_string(),
+                validity_hours: 1,
+                signing_keys: vec![SigningKey {
+                    id: "current".to_string(),
+                    secret: Secret::new("signing-secret".to_string()),
+                }],
+            }),
+        )
+    }
+
+    #[test]
+    fn token_links_carry_a_token_matching_the_hash_to_store() {
+        let email = renderer(ConfirmationLinkMode::Token)
+            .render(Uuid::new_v4())
+            .unwrap();
+
+        let link = email
+            .text_body
+            .split_whitespace()
+            .find(|word| word.contains("/subscriptions/confirm"))
+            .unwrap();
+        let link = Url::parse(link).unwrap();
+        let (_, token) = link
+            .query_pairs()
+            .find(|(key, _)| key == "subscription_token")
+            .unwrap();
+        let token = SubscriptionToken::parse(token.into_owned()).unwrap();
+        assert_eq!(
+            email.token_hash,
+            Some(token.hash(&Secret::new("hmac-secret".to_string())))
+        );
+        assert!(email.html_body.contains(link.as_str()));
+    }
+
+    #[test]
+    fn every_rendering_carries_a_new_token() {
+        let renderer = renderer(ConfirmationLinkMode::Token);
+        let subscriber_id = Uuid::new_v4();
+
+        let first = renderer.render(subscriber_id).unwrap();
+        let second = renderer.render(subscriber_id).unwrap();
+
+        assert_ne!(first.token_hash, second.token_hash);
+    }
+
+    #[test]
+    fn signed_links_carry_the_id_of_the_subscriber() {
+        let subscriber_id = Uuid::new_v4();
+
+        let email = renderer(ConfirmationLinkMode::Signed)
+            .render(subscriber_id)
+            .unwrap();
+
+        assert!(email
+            .text_body
+            .contains(&format!("subscriber_id={}", subscriber_id)));
+        assert_eq!(email.token_hash, None);
+    }
+}
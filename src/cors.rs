@@ -0,0 +1,87 @@
+use actix_cors::Cors;
+use actix_web::http::header::CONTENT_TYPE;
+
+/// Lets the allowed sites call the signup endpoints from the browser, as the signup
+/// widget does. Requests from any other origin are still served, the browser
+/// just keeps the response from the page, so plain HTML forms keep working.
+pub fn signup_cors(allowed_origins: &[String]) -> Cors {
+    allowed_origins.iter().fold(
+        Cors::default()
+            .allowed_methods(["GET", "POST"])
+            .allowed_header(CONTENT_TYPE)
+            .max_age(3600),
+        |cors, origin| cors.allowed_origin(origin),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS,
+        ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+    };
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::*;
+
+    macro_rules! app {
+        () => {
+            test::init_service(
+                App::new().service(
+                    web::resource("/subscribe")
+                        .wrap(signup_cors(&["https://www.example.com".to_string()]))
+                        .route(web::post().to(HttpResponse::Ok)),
+                ),
+            )
+            .await
+        };
+    }
+
+    fn preflight(origin: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/subscribe")
+            .insert_header((ORIGIN, origin))
+            .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "content-type"))
+    }
+
+    #[actix_web::test]
+    async fn allowed_origins_may_post_json() {
+        let app = app!();
+
+        let response =
+            test::call_service(&app, preflight("https://www.example.com").to_request()).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://www.example.com"
+        );
+        assert!(headers
+            .get(ACCESS_CONTROL_ALLOW_HEADERS)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("content-type"));
+    }
+
+    #[actix_web::test]
+    async fn other_origins_are_not_allowed_to_read_the_response() {
+        let app = app!();
+
+        let response =
+            test::call_service(&app, preflight("https://evil.example.com").to_request()).await;
+        assert_ne!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::post()
+            .uri("/subscribe")
+            .insert_header((ORIGIN, "https://evil.example.com"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
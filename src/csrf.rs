@@ -0,0 +1,354 @@
+use std::future::{ready, Ready};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::configuration::CookieSettings;
+use crate::startup::HmacSecret;
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_FIELD: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Signup forms are embedded on other sites, which can never hold our cookie.
+const EXEMPT_PATHS: &[&str] = &["/subscribe"];
+
+/// The content types an HTML form can be posted with from another site.
+const FORM_CONTENT_TYPES: &[&str] = &[
+    "application/x-www-form-urlencoded",
+    "multipart/form-data",
+    "text/plain",
+];
+
+const NONCE_LENGTH: usize = 32;
+
+/// A random nonce and its signature, so the application only accepts tokens it issued.
+/// Handlers rendering a form take it as an argument and embed it as the `csrf_token` field.
+#[derive(Clone, Debug)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    fn generate(secret: &Secret<String>) -> CsrfToken {
+        let nonce = Alphanumeric.sample_string(&mut rand::thread_rng(), NONCE_LENGTH);
+        let signature = hex::encode(mac(secret, &nonce).finalize().into_bytes());
+        CsrfToken(format!("{}.{}", nonce, signature))
+    }
+
+    fn parse(secret: &Secret<String>, token: &str) -> Option<CsrfToken> {
+        let (nonce, signature) = token.split_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        mac(secret, nonce)
+            .verify_slice(&signature)
+            .ok()
+            .map(|_| CsrfToken(token.to_string()))
+    }
+
+    /// Compares the tokens in constant time.
+    fn matches(&self, secret: &Secret<String>, submitted: &str) -> bool {
+        let expected = mac(secret, &self.0).finalize().into_bytes();
+        mac(secret, submitted).verify_slice(&expected).is_ok()
+    }
+}
+
+impl AsRef<str> for CsrfToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<CsrfToken, actix_web::Error>>;
+
+    /// Reuses the token from the request's cookie, or issues a new one that
+    /// `csrf_protection` sets as a cookie on the response.
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let secret = match request.app_data::<web::Data<HmacSecret>>() {
+            Some(hmac_secret) => &hmac_secret.0,
+            None => {
+                return ready(Err(ErrorInternalServerError(
+                    "HmacSecret is not configured",
+                )))
+            }
+        };
+        if let Some(token) = cookie_token(request, secret) {
+            return ready(Ok(token));
+        }
+        if let Some(IssuedCsrfToken(token)) = request.extensions().get::<IssuedCsrfToken>() {
+            return ready(Ok(token.clone()));
+        }
+        let token = CsrfToken::generate(secret);
+        request
+            .extensions_mut()
+            .insert(IssuedCsrfToken(token.clone()));
+        ready(Ok(token))
+    }
+}
+
+struct IssuedCsrfToken(CsrfToken);
+
+#[derive(Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+/// Double-submit protection: a form post must carry the token from the
+/// `csrf_token` cookie, either as the `csrf_token` field or in the
+/// `X-CSRF-Token` header. Requests that are not form posts, such as the JSON
+/// API authenticated with bearer tokens, are left alone.
+pub async fn csrf_protection(
+    mut request: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let secret = match request.app_data::<web::Data<HmacSecret>>() {
+        Some(hmac_secret) => hmac_secret.0.clone(),
+        None => return Err(ErrorInternalServerError("HmacSecret is not configured")),
+    };
+
+    if is_form_post(&request) && !EXEMPT_PATHS.contains(&request.path()) {
+        let expected = cookie_token(request.request(), &secret);
+        let submitted = submitted_token(&mut request).await?;
+        let is_valid = match (expected, submitted) {
+            (Some(expected), Some(submitted)) => expected.matches(&secret, &submitted),
+            _ => false,
+        };
+        if !is_valid {
+            tracing::warn!("Rejecting a form post without a valid CSRF token");
+            return Ok(request.into_response(HttpResponse::Forbidden().finish()));
+        }
+    }
+
+    let mut response = next.call(request).await?.map_into_boxed_body();
+    let issued = response
+        .request()
+        .extensions_mut()
+        .remove::<IssuedCsrfToken>();
+    if let Some(IssuedCsrfToken(token)) = issued {
+        let cookie = match response.request().app_data::<web::Data<CookieSettings>>() {
+            Some(cookies) => cookies.cookie(CSRF_COOKIE, token.0),
+            None => return Err(ErrorInternalServerError("CookieSettings is not configured")),
+        };
+        response.response_mut().add_cookie(&cookie)?;
+    }
+    Ok(response)
+}
+
+fn is_form_post(request: &ServiceRequest) -> bool {
+    let is_safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    !is_safe
+        && FORM_CONTENT_TYPES
+            .iter()
+            .any(|form_content_type| content_type.starts_with(form_content_type))
+}
+
+/// The header wins. Otherwise the body is read for the form field, then put
+/// back for the handler.
+async fn submitted_token(request: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    if let Some(token) = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|token| token.to_str().ok())
+    {
+        return Ok(Some(token.to_string()));
+    }
+    if request.content_type() != "application/x-www-form-urlencoded" {
+        return Ok(None);
+    }
+
+    let body = request.extract::<web::Bytes>().await?;
+    let form = serde_urlencoded::from_bytes::<CsrfForm>(&body).ok();
+    request.set_payload(body.into());
+    Ok(form.and_then(|form| form.csrf_token))
+}
+
+fn cookie_token(request: &HttpRequest, secret: &Secret<String>) -> Option<CsrfToken> {
+    request
+        .cookie(CSRF_COOKIE)
+        .and_then(|cookie| CsrfToken::parse(secret, cookie.value()))
+}
+
+fn mac(secret: &Secret<String>, value: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(value.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::cookie::Cookie;
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    use crate::configuration::CookieSameSite;
+
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("hmac-secret".to_string())
+    }
+
+    #[derive(Deserialize)]
+    struct Preferences {
+        frequency: String,
+    }
+
+    async fn preferences_form(token: CsrfToken) -> HttpResponse {
+        HttpResponse::Ok().body(token.as_ref().to_string())
+    }
+
+    async fn update_preferences(form: web::Form<Preferences>) -> HttpResponse {
+        HttpResponse::Ok().body(form.0.frequency)
+    }
+
+    macro_rules! app {
+        () => {
+            test::init_service(
+                App::new()
+                    .wrap(from_fn(csrf_protection))
+                    .app_data(web::Data::new(HmacSecret(secret())))
+                    .app_data(web::Data::new(CookieSettings {
+                        secure: true,
+                        same_site: CookieSameSite::Strict,
+                    }))
+                    .route("/preferences", web::get().to(preferences_form))
+                    .route("/preferences", web::post().to(update_preferences))
+                    .route("/subscribe", web::post().to(update_preferences)),
+            )
+            .await
+        };
+    }
+
+    fn form_post(uri: &str, body: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header((CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload(body.to_string())
+    }
+
+    #[actix_web::test]
+    async fn rendering_a_form_issues_a_token_in_a_strict_cookie() {
+        let app = app!();
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/preferences").to_request(),
+        )
+        .await;
+
+        let cookie = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == CSRF_COOKIE)
+            .unwrap()
+            .into_owned();
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(
+            cookie.same_site(),
+            Some(actix_web::cookie::SameSite::Strict)
+        );
+        let body = test::read_body(response).await;
+        assert_eq!(body, cookie.value());
+    }
+
+    #[actix_web::test]
+    async fn a_form_post_with_the_token_from_its_cookie_is_accepted() {
+        let app = app!();
+        let token = CsrfToken::generate(&secret());
+
+        let request = form_post(
+            "/preferences",
+            &format!("frequency=weekly&csrf_token={}", token.as_ref()),
+        )
+        .cookie(Cookie::new(CSRF_COOKIE, token.as_ref().to_string()))
+        .to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).await, "weekly");
+    }
+
+    #[actix_web::test]
+    async fn the_token_can_be_sent_in_a_header() {
+        let app = app!();
+        let token = CsrfToken::generate(&secret());
+
+        let request = form_post("/preferences", "frequency=weekly")
+            .cookie(Cookie::new(CSRF_COOKIE, token.as_ref().to_string()))
+            .insert_header((CSRF_HEADER, token.as_ref()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn form_posts_without_a_matching_token_are_forbidden() {
+        let app = app!();
+        let token = CsrfToken::generate(&secret());
+        let other = CsrfToken::generate(&secret());
+        let forged = CsrfToken::generate(&Secret::new("another-secret".to_string()));
+
+        let cases = [
+            (None, None),
+            (Some(&token), None),
+            (None, Some(&token)),
+            (Some(&token), Some(&other)),
+            (Some(&forged), Some(&forged)),
+        ];
+        for (cookie, field) in cases {
+            let body = match field {
+                Some(field) => format!("frequency=weekly&csrf_token={}", field.as_ref()),
+                None => "frequency=weekly".to_string(),
+            };
+            let mut request = form_post("/preferences", &body);
+            if let Some(cookie) = cookie {
+                request = request.cookie(Cookie::new(CSRF_COOKIE, cookie.as_ref().to_string()));
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[actix_web::test]
+    async fn exempt_paths_and_json_requests_are_not_checked() {
+        let app = app!();
+
+        let response = test::call_service(
+            &app,
+            form_post("/subscribe", "frequency=weekly").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::post()
+            .uri("/preferences")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload("{}")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_ne!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,67 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub const CONFIRMATION_TEMPLATE: &str = "subscription_confirmation";
+pub const NEWSLETTER_ISSUE_TEMPLATE: &str = "newsletter_issue";
+
+pub enum DeliveryStatus {
+    /// Accepted by the email provider for delivery.
+    Sent,
+    /// Refused by the email provider, e.g. because the recipient is inactive.
+    Rejected,
+    /// We could not get an answer from the email provider.
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Rejected => "rejected",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+pub struct NewDelivery<'a> {
+    pub recipient: &'a str,
+    pub template: &'a str,
+    pub subject: &'a str,
+    pub provider_message_id: Option<&'a str>,
+    pub status: DeliveryStatus,
+    pub error: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Record email deliveries in the database",
+    skip(pool, deliveries),
+    fields(deliveries = deliveries.len())
+)]
+pub async fn record_deliveries(
+    pool: &PgPool,
+    deliveries: &[NewDelivery<'_>],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let attempted_at = Utc::now();
+    for delivery in deliveries {
+        sqlx::query!(
+            r#"
+            INSERT INTO email_deliveries
+                (id, recipient, template, subject, provider_message_id, status, error, attempted_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            Uuid::new_v4(),
+            delivery.recipient,
+            delivery.template,
+            delivery.subject,
+            delivery.provider_message_id,
+            delivery.status.as_str(),
+            delivery.error,
+            attempted_at,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    transaction.commit().await
+}
@@ -1,6 +1,14 @@
 mod new_subscriber;
+mod newsletter_template;
+mod signed_confirmation;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
+mod subscription_token;
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_template::{MergeFields, NewsletterTemplate};
+pub use signed_confirmation::SignedConfirmation;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_status::SubscriptionStatus;
+pub use subscription_token::SubscriptionToken;
@@ -0,0 +1,170 @@
+/// A merge field that can be referenced from newsletter content as `{{ field }}`,
+/// optionally with a fallback for missing values: `{{ field | fallback }}`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MergeField {
+    Name,
+    Email,
+    UnsubscribeUrl,
+    ArchiveUrl,
+}
+
+impl MergeField {
+    fn parse(s: &str) -> Result<MergeField, String> {
+        match s {
+            "name" => Ok(MergeField::Name),
+            "email" => Ok(MergeField::Email),
+            "unsubscribe_url" => Ok(MergeField::UnsubscribeUrl),
+            "archive_url" => Ok(MergeField::ArchiveUrl),
+            x => Err(format!("{{{{{}}}}} is not a supported merge field", x)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Text(String),
+    Field {
+        field: MergeField,
+        fallback: Option<String>,
+    },
+}
+
+/// Per-recipient values for the merge fields. Fields left as `None` are rendered
+/// using the placeholder's fallback, or as an empty string if it has none.
+#[derive(Debug, Default)]
+pub struct MergeFields<'a> {
+    pub name: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub unsubscribe_url: Option<&'a str>,
+    pub archive_url: Option<&'a str>,
+}
+
+impl<'a> MergeFields<'a> {
+    fn get(&self, field: MergeField) -> Option<&'a str> {
+        match field {
+            MergeField::Name => self.name,
+            MergeField::Email => self.email,
+            MergeField::UnsubscribeUrl => self.unsubscribe_url,
+            MergeField::ArchiveUrl => self.archive_url,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewsletterTemplate(Vec<Segment>);
+
+impl NewsletterTemplate {
+    pub fn parse(s: String) -> Result<NewsletterTemplate, String> {
+        let mut segments = Vec::new();
+        let mut rest = s.as_str();
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let after_open = &rest[start + 2..];
+            let end = after_open
+                .find("}}")
+                .ok_or_else(|| "Newsletter content contains an unclosed merge field".to_string())?;
+
+            let placeholder = &after_open[..end];
+            let (field, fallback) = match placeholder.split_once('|') {
+                Some((field, fallback)) => (field, Some(fallback.trim().to_string())),
+                None => (placeholder, None),
+            };
+            segments.push(Segment::Field {
+                field: MergeField::parse(field.trim())?,
+                fallback,
+            });
+
+            rest = &after_open[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+
+        Ok(Self(segments))
+    }
+
+    pub fn render(&self, fields: &MergeFields) -> String {
+        let mut rendered = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Field { field, fallback } => {
+                    let value = fields
+                        .get(*field)
+                        .filter(|value| !value.trim().is_empty())
+                        .or(fallback.as_deref())
+                        .unwrap_or_default();
+                    rendered.push_str(value);
+                }
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::*;
+
+    fn fields() -> MergeFields<'static> {
+        MergeFields {
+            name: Some("Ursula"),
+            email: Some("ursula@gmail.com"),
+            unsubscribe_url: Some("http://127.0.0.1/unsubscribe"),
+            archive_url: Some("http://127.0.0.1/archive"),
+        }
+    }
+
+    #[test]
+    fn content_without_merge_fields_is_rendered_as_is() {
+        let template = NewsletterTemplate::parse("Hello there!".to_string()).unwrap();
+        assert_eq!(template.render(&fields()), "Hello there!");
+    }
+
+    #[test]
+    fn all_supported_merge_fields_are_rendered() {
+        let template = NewsletterTemplate::parse(
+            "Hi {{name}} ({{ email }}), read online at {{archive_url}} or leave at {{unsubscribe_url}}"
+                .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render(&fields()),
+            "Hi Ursula (ursula@gmail.com), read online at http://127.0.0.1/archive \
+            or leave at http://127.0.0.1/unsubscribe"
+        );
+    }
+
+    #[test]
+    fn missing_values_are_rendered_with_the_fallback() {
+        let template = NewsletterTemplate::parse("Hi {{ name | there }}!".to_string()).unwrap();
+        assert_eq!(template.render(&MergeFields::default()), "Hi there!");
+    }
+
+    #[test]
+    fn missing_values_without_a_fallback_are_rendered_empty() {
+        let template = NewsletterTemplate::parse("Archive: {{archive_url}}".to_string()).unwrap();
+        assert_eq!(template.render(&MergeFields::default()), "Archive: ");
+    }
+
+    #[test]
+    fn unknown_merge_fields_are_rejected() {
+        assert_err!(NewsletterTemplate::parse("Hi {{first_name}}".to_string()));
+    }
+
+    #[test]
+    fn unclosed_merge_fields_are_rejected() {
+        assert_err!(NewsletterTemplate::parse("Hi {{name".to_string()));
+    }
+
+    #[test]
+    fn a_single_brace_is_treated_as_text() {
+        assert_ok!(NewsletterTemplate::parse("a { b } c".to_string()));
+    }
+}
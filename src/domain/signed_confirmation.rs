@@ -0,0 +1,112 @@
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// The claims carried by a stateless confirmation link. They are signed with a
+/// key from the configured key set, so no token needs to be stored to check them.
+#[derive(Debug)]
+pub struct SignedConfirmation {
+    pub subscriber_id: Uuid,
+    pub list: String,
+    pub expires_at: i64,
+}
+
+impl SignedConfirmation {
+    pub fn new(subscriber_id: Uuid, list: String, validity: Duration) -> SignedConfirmation {
+        Self {
+            subscriber_id,
+            list,
+            expires_at: (Utc::now() + validity).timestamp(),
+        }
+    }
+
+    pub fn signature(&self, secret: &Secret<String>) -> String {
+        hex::encode(self.mac(secret).finalize().into_bytes())
+    }
+
+    /// Checks `signature` against these claims in constant time.
+    pub fn has_valid_signature(&self, secret: &Secret<String>, signature: &str) -> bool {
+        match hex::decode(signature) {
+            Ok(signature) => self.mac(secret).verify_slice(&signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now.timestamp() >= self.expires_at
+    }
+
+    fn mac(&self, secret: &Secret<String>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(format!("{}.{}.{}", self.subscriber_id, self.list, self.expires_at).as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    fn confirmation() -> SignedConfirmation {
+        SignedConfirmation::new(Uuid::new_v4(), "newsletter".to_string(), Duration::hours(1))
+    }
+
+    #[test]
+    fn a_signature_made_with_the_same_key_is_valid() {
+        let confirmation = confirmation();
+        let signature = confirmation.signature(&secret());
+        assert!(confirmation.has_valid_signature(&secret(), &signature));
+    }
+
+    #[test]
+    fn a_signature_made_with_another_key_is_invalid() {
+        let confirmation = confirmation();
+        let signature = confirmation.signature(&Secret::new("another-key".to_string()));
+        assert!(!confirmation.has_valid_signature(&secret(), &signature));
+    }
+
+    #[test]
+    fn tampering_with_any_claim_invalidates_the_signature() {
+        let original = confirmation();
+        let signature = original.signature(&secret());
+
+        let other_subscriber = SignedConfirmation {
+            subscriber_id: Uuid::new_v4(),
+            list: original.list.clone(),
+            expires_at: original.expires_at,
+        };
+        let other_list = SignedConfirmation {
+            subscriber_id: original.subscriber_id,
+            list: "another-list".to_string(),
+            expires_at: original.expires_at,
+        };
+        let later_expiry = SignedConfirmation {
+            subscriber_id: original.subscriber_id,
+            list: original.list.clone(),
+            expires_at: original.expires_at + 3600,
+        };
+
+        assert!(!other_subscriber.has_valid_signature(&secret(), &signature));
+        assert!(!other_list.has_valid_signature(&secret(), &signature));
+        assert!(!later_expiry.has_valid_signature(&secret(), &signature));
+    }
+
+    #[test]
+    fn a_signature_that_is_not_hex_is_invalid() {
+        assert!(!confirmation().has_valid_signature(&secret(), "not-hex"));
+    }
+
+    #[test]
+    fn a_confirmation_expires_after_its_validity() {
+        let confirmation = confirmation();
+        assert!(!confirmation.is_expired(Utc::now()));
+        assert!(confirmation.is_expired(Utc::now() + Duration::hours(2)));
+    }
+}
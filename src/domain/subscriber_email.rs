@@ -1,7 +1,12 @@
+use std::fmt;
+
 use serde::Serialize;
 use validator::validate_email;
 
-#[derive(Debug, Serialize)]
+use crate::redaction::redact_email;
+
+/// `Debug` and `Display` follow the redaction policy; use `as_ref` for the address itself.
+#[derive(Serialize)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
@@ -9,7 +14,10 @@ impl SubscriberEmail {
         if validate_email(&s) {
             Ok(Self(s))
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            Err(format!(
+                "{} is not a valid subscriber email.",
+                redact_email(&s)
+            ))
         }
     }
 }
@@ -20,6 +28,20 @@ impl AsRef<str> for SubscriberEmail {
     }
 }
 
+impl fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SubscriberEmail")
+            .field(&redact_email(&self.0))
+            .finish()
+    }
+}
+
+impl fmt::Display for SubscriberEmail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact_email(&self.0))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use claim::assert_err;
@@ -46,6 +68,21 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn debug_and_display_do_not_reveal_the_address() {
+        let email = SubscriberEmail::parse("ursula@example.com".to_string()).unwrap();
+
+        assert!(!format!("{:?}", email).contains("ursula"));
+        assert!(!email.to_string().contains("ursula"));
+        assert_eq!(email.as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn errors_do_not_reveal_the_rejected_value() {
+        let error = SubscriberEmail::parse("ursula.example.com".to_string()).unwrap_err();
+        assert!(!error.contains("ursula"));
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(String);
 
@@ -1,6 +1,10 @@
+use std::fmt;
+
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Debug)]
+use crate::redaction::redact_name;
+
+/// `Debug` and `Display` follow the redaction policy; use `as_ref` for the name itself.
 pub struct SubscriberName(String);
 
 impl SubscriberName {
@@ -11,7 +15,10 @@ impl SubscriberName {
         let contains_forbidden_chars = s.chars().any(|each| forbidden_chars.contains(&each));
 
         if is_empty_or_whitespace || is_too_long || contains_forbidden_chars {
-            Err(format!("{} is not a valid subscriber name", s))
+            Err(format!(
+                "{} is not a valid subscriber name",
+                redact_name(&s)
+            ))
         } else {
             Ok(Self(s))
         }
@@ -24,6 +31,20 @@ impl AsRef<str> for SubscriberName {
     }
 }
 
+impl fmt::Debug for SubscriberName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SubscriberName")
+            .field(&redact_name(&self.0))
+            .finish()
+    }
+}
+
+impl fmt::Display for SubscriberName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact_name(&self.0))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use claim::{assert_err, assert_ok};
@@ -61,6 +82,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn debug_and_display_do_not_reveal_the_name() {
+        let name = SubscriberName::parse("Ursula Le Guin".to_string()).unwrap();
+
+        assert!(!format!("{:?}", name).contains("Ursula"));
+        assert!(!name.to_string().contains("Ursula"));
+    }
+
     #[test]
     fn a_valid_name_is_parsed_successfully() {
         let name = "Arun Manivannan".to_string();
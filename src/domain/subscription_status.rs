@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a subscriber is in their lifecycle. Every status change goes through
+/// `transition_to`, which only allows pending -> confirmed -> unsubscribed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionStatus {
+    /// Moving to the status a subscriber is already in is allowed, so that
+    /// clicking a confirmation link twice is harmless.
+    pub fn transition_to(self, next: SubscriptionStatus) -> Result<SubscriptionStatus, String> {
+        use SubscriptionStatus::*;
+
+        match (self, next) {
+            (current, next) if current == next => Ok(next),
+            (PendingConfirmation, Confirmed)
+            | (PendingConfirmation, Unsubscribed)
+            | (Confirmed, Unsubscribed) => Ok(next),
+            (current, next) => Err(format!(
+                "A subscriber cannot go from {} to {}",
+                current.as_str(),
+                next.as_str()
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::SubscriptionStatus::*;
+
+    #[test]
+    fn a_pending_subscriber_can_confirm_or_unsubscribe() {
+        assert_eq!(
+            assert_ok!(PendingConfirmation.transition_to(Confirmed)),
+            Confirmed
+        );
+        assert_ok!(PendingConfirmation.transition_to(Unsubscribed));
+    }
+
+    #[test]
+    fn a_confirmed_subscriber_can_unsubscribe() {
+        assert_ok!(Confirmed.transition_to(Unsubscribed));
+    }
+
+    #[test]
+    fn a_subscriber_cannot_go_back() {
+        assert_err!(Confirmed.transition_to(PendingConfirmation));
+        assert_err!(Unsubscribed.transition_to(Confirmed));
+        assert_err!(Unsubscribed.transition_to(PendingConfirmation));
+    }
+
+    #[test]
+    fn staying_in_the_same_status_is_allowed() {
+        for status in [PendingConfirmation, Confirmed, Unsubscribed] {
+            assert_ok!(status.transition_to(status));
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+const TOKEN_LENGTH: usize = 25;
+
+#[derive(Debug)]
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    pub fn generate() -> SubscriptionToken {
+        let mut rng = rand::thread_rng();
+        Self(Alphanumeric.sample_string(&mut rng, TOKEN_LENGTH))
+    }
+
+    pub fn parse(s: String) -> Result<SubscriptionToken, String> {
+        let is_valid_length = s.chars().count() == TOKEN_LENGTH;
+        let is_alphanumeric = s.chars().all(|c| c.is_ascii_alphanumeric());
+
+        if is_valid_length && is_alphanumeric {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not a valid subscription token", s))
+        }
+    }
+
+    /// The keyed hash of the token, which is what gets persisted.
+    pub fn hash(&self, secret: &Secret<String>) -> String {
+        hex::encode(self.mac(secret).finalize().into_bytes())
+    }
+
+    fn mac(&self, secret: &Secret<String>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(self.0.as_bytes());
+        mac
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn generated_tokens_are_valid() {
+        let token = SubscriptionToken::generate();
+        assert_ok!(SubscriptionToken::parse(token.as_ref().to_string()));
+    }
+
+    #[test]
+    fn tokens_of_the_wrong_length_are_rejected() {
+        assert_err!(SubscriptionToken::parse("abc".to_string()));
+        assert_err!(SubscriptionToken::parse("a".repeat(26)));
+    }
+
+    #[test]
+    fn tokens_with_non_alphanumeric_characters_are_rejected() {
+        assert_err!(SubscriptionToken::parse(format!("{}'", "a".repeat(24))));
+    }
+
+    #[test]
+    fn the_hash_does_not_contain_the_token() {
+        let token = SubscriptionToken::generate();
+        let hash = token.hash(&secret());
+        assert!(!hash.contains(token.as_ref()));
+    }
+
+    #[test]
+    fn the_same_token_and_secret_always_give_the_same_hash() {
+        let token = SubscriptionToken::generate();
+        assert_eq!(token.hash(&secret()), token.hash(&secret()));
+    }
+
+    #[test]
+    fn the_hash_depends_on_the_secret() {
+        let token = SubscriptionToken::generate();
+        let other = Secret::new("another-key".to_string());
+        assert_ne!(token.hash(&secret()), token.hash(&other));
+    }
+
+    #[test]
+    fn different_tokens_have_different_hashes() {
+        assert_ne!(
+            SubscriptionToken::generate().hash(&secret()),
+            SubscriptionToken::generate().hash(&secret())
+        );
+    }
+}
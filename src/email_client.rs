@@ -1,11 +1,17 @@
 use std::time::Duration;
 
+use opentelemetry::propagation::Injector;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::domain::SubscriberEmail;
 
+/// Postmark accepts at most this many messages in a single batch request.
+pub const MAX_BATCH_SIZE: usize = 500;
+
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
@@ -15,12 +21,38 @@ pub struct EmailClient {
 
 #[derive(Serialize)]
 #[serde(rename_all = "PascalCase")]
-struct SendEmailRequest {
-    from: String,
-    to: String,
-    subject: String,
-    html_body: String,
-    text_body: String,
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SendEmailResponse {
+    pub error_code: i64,
+    pub message: String,
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    pub submitted_at: Option<String>,
+    pub to: Option<String>,
+}
+
+pub struct Email {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+#[derive(Debug)]
+pub enum BatchSendError {
+    /// Postmark refused this message, e.g. because the recipient is marked as inactive.
+    Rejected { error_code: i64, message: String },
+    /// The batch request carrying this message failed as a whole, so it was not sent.
+    RequestFailed(String),
 }
 
 impl EmailClient {
@@ -32,41 +64,171 @@ impl EmailClient {
     ) -> Self {
         let http_client = Client::builder().timeout(timeout).build().unwrap();
         Self {
-            http_client: http_client,
-            authorization_token: authorization_token,
-            base_url: base_url,
-            sender: sender,
+            http_client,
+            authorization_token,
+            base_url,
+            sender,
         }
     }
 
+    /// Any 2xx answer counts as sent. The response body is only read for the
+    /// message ID, so a body that can't be parsed doesn't get the email sent twice.
     pub async fn send_email(
         &self,
         recipient: SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<SendEmailResponse, reqwest::Error> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
-            from: self.sender.as_ref().to_string(),
-            to: recipient.as_ref().to_string(),
-            subject: subject.to_string(),
-            html_body: html_content.to_string(),
-            text_body: text_content.to_string(),
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
         };
 
-        let builder = self
+        let response = self
             .http_client
             .post(&url)
             .header(
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
             )
+            .headers(trace_context_headers())
             .json(&request_body)
             .send()
             .await?
             .error_for_status()?;
-        Ok(())
+
+        let body = response.bytes().await.unwrap_or_default();
+        Ok(serde_json::from_slice(&body).unwrap_or_else(|e| {
+            tracing::warn!(
+                "Postmark accepted an email but its response could not be read: {}",
+                e
+            );
+            accepted_without_details()
+        }))
+    }
+
+    /// Sends `emails` through Postmark's batch endpoint, in groups of at most
+    /// `MAX_BATCH_SIZE` messages per request.
+    ///
+    /// The returned results line up with `emails`, so callers can retry or
+    /// suppress individual messages without resending the whole batch.
+    pub async fn send_email_batch(
+        &self,
+        emails: &[Email],
+    ) -> Vec<Result<SendEmailResponse, BatchSendError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_batch_request(chunk).await {
+                Ok(responses) => {
+                    outcomes.extend(responses.into_iter().map(|response| {
+                        if response.error_code == 0 {
+                            Ok(response)
+                        } else {
+                            Err(BatchSendError::Rejected {
+                                error_code: response.error_code,
+                                message: response.message,
+                            })
+                        }
+                    }));
+                }
+                Err(e) => {
+                    tracing::error!("Failed to send a batch of {} emails: {:?}", chunk.len(), e);
+                    outcomes.extend(
+                        chunk
+                            .iter()
+                            .map(|_| Err(BatchSendError::RequestFailed(e.to_string()))),
+                    );
+                }
+            }
+        }
+        outcomes
+    }
+
+    async fn send_batch_request(
+        &self,
+        emails: &[Email],
+    ) -> Result<Vec<SendEmailResponse>, reqwest::Error> {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<SendEmailRequest> = emails
+            .iter()
+            .map(|email| SendEmailRequest {
+                from: self.sender.as_ref(),
+                to: email.recipient.as_ref(),
+                subject: &email.subject,
+                html_body: &email.html_content,
+                text_body: &email.text_content,
+            })
+            .collect();
+
+        let response = self
+            .http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .headers(trace_context_headers())
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // Postmark has accepted the batch, so it must not be sent again. Its
+        // results, one per message in request order, only add rejections and IDs:
+        // a message it did not report on counts as accepted.
+        let body = response.bytes().await.unwrap_or_default();
+        let mut responses: Vec<SendEmailResponse> =
+            serde_json::from_slice(&body).unwrap_or_else(|e| {
+                tracing::warn!(
+                    "Postmark accepted a batch of {} emails but its response could not be read: {}",
+                    emails.len(),
+                    e
+                );
+                Vec::new()
+            });
+        responses.truncate(emails.len());
+        responses.resize_with(emails.len(), accepted_without_details);
+        Ok(responses)
+    }
+}
+
+/// Stands in for the result of a message Postmark accepted without saying more about it.
+fn accepted_without_details() -> SendEmailResponse {
+    SendEmailResponse {
+        error_code: 0,
+        message: "OK".to_string(),
+        message_id: None,
+        submitted_at: None,
+        to: None,
+    }
+}
+
+/// The `traceparent` header for the current span, so Postmark requests show up
+/// in the same trace as the work that triggered them.
+fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
     }
 }
 
@@ -145,6 +307,92 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn send_email_carries_the_current_trace_context() {
+        use opentelemetry::trace::{TraceContextExt, TracerProvider};
+        use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider};
+        use tracing::Instrument;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(header_exists("traceparent"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = SdkTracerProvider::builder().build().tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span = tracing::info_span!("Send a confirmation email");
+        let trace_id = span.context().span().span_context().trace_id();
+        let _ = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .instrument(span)
+            .await;
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let traceparent = request.headers.get(&"traceparent".into()).unwrap().last();
+        assert!(traceparent.as_str().contains(&trace_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_provider_message_id() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response_body = serde_json::json!({
+            "To": "receiver@example.com",
+            "SubmittedAt": "2023-02-01T10:00:00.0000000-05:00",
+            "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+            "ErrorCode": 0,
+            "Message": "OK"
+        });
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        let response = assert_ok!(outcome);
+        assert_eq!(
+            response.message_id.as_deref(),
+            Some("0a129aee-e1cd-480d-b08d-4f48548ff48d")
+        );
+        assert_eq!(
+            response.submitted_at.as_deref(),
+            Some("2023-02-01T10:00:00.0000000-05:00")
+        );
+        assert_eq!(response.error_code, 0);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_without_a_message_id_if_the_response_is_unreadable() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        let response = assert_ok!(outcome);
+        assert_eq!(response.message_id, None);
+    }
+
     #[tokio::test]
     async fn send_email_fails_if_the_server_returns_500() {
         let mock_server = MockServer::start().await;
@@ -1,6 +1,20 @@
+pub mod audit;
+pub mod authentication;
+pub mod bot_protection;
 pub mod configuration;
+pub mod confirmation_email;
+pub mod cors;
+pub mod csrf;
+pub mod delivery_log;
 pub mod domain;
 pub mod email_client;
+pub mod migrations;
+pub mod outbox_dispatcher;
+pub mod redaction;
+pub mod reload;
 pub mod routes;
+pub mod security_headers;
 pub mod startup;
+pub mod subscriber_repository;
 pub mod telemetry;
+pub mod tls;
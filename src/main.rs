@@ -1,27 +1,175 @@
-pub mod domain;
-pub mod email_client;
-
-use std::{net::TcpListener, time::Duration};
-
-use sqlx::postgres::PgPoolOptions;
-use zero2prod::email_client::EmailClient;
+use std::fmt::{Debug, Display};
 
+use anyhow::Context;
+use tokio::task::JoinError;
+use zero2prod::authentication::{issue_api_key, NewApiKey, Scope};
+use zero2prod::configuration::{
+    deprecated_environment_variables, describe_configuration, Settings,
+};
+use zero2prod::migrations::run_migrations;
+use zero2prod::outbox_dispatcher::run_worker_until_stopped;
+use zero2prod::redaction::init_redaction;
 use zero2prod::startup::Application;
-use zero2prod::telemetry::init_subscriber;
+use zero2prod::telemetry::{get_log_sink, get_tracer_provider, init_subscriber, LogFilter};
 use zero2prod::{configuration::get_configuration, telemetry::get_subscriber};
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let trace_subscriber =
-        get_subscriber("zero2prod".to_string(), "info".to_string(), std::io::stdout);
-    init_subscriber(trace_subscriber);
+async fn main() -> anyhow::Result<()> {
+    if let Some("print-config") = std::env::args().nth(1).as_deref() {
+        return print_configuration();
+    }
 
     let configuration = get_configuration().expect("Unable to load configuration");
 
-    let application = Application::build(configuration).await?;
-    let application_task = application.run_until_stopped();
+    let tracer_provider = configuration
+        .telemetry
+        .otlp
+        .as_ref()
+        .map(|otlp| get_tracer_provider("zero2prod".to_string(), otlp))
+        .transpose()
+        .context("Unable to set up the OTLP exporter")?;
+    let log_settings = &configuration.telemetry.log;
+    let log_sink = get_log_sink(log_settings).context("Unable to open the log file")?;
+    let (trace_subscriber, log_filter) = get_subscriber(
+        "zero2prod".to_string(),
+        log_settings.filter.clone(),
+        log_settings.format,
+        log_sink,
+        tracer_provider.as_ref(),
+    );
+    init_subscriber(trace_subscriber);
+    for name in deprecated_environment_variables() {
+        tracing::warn!(
+            "{} uses the deprecated single-underscore form, which will stop being read in a future release. Nest with a double underscore instead, e.g. APP__DATABASE__HOST",
+            name
+        );
+    }
+    init_redaction(
+        configuration.telemetry.pii,
+        configuration.telemetry.redaction_key.clone(),
+    );
+
+    let outcome = run(configuration, log_filter).await;
+    if let Some(tracer_provider) = tracer_provider {
+        // Flushes the spans that are still waiting to be exported.
+        let _ = tracer_provider.shutdown();
+    }
+    outcome
+}
+
+async fn run(configuration: Settings, log_filter: LogFilter) -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("issue-api-key") => return issue_bootstrap_api_key(configuration, &args[1..]).await,
+        // Lets deploy pipelines migrate once, before starting any instance of the new build.
+        Some("--migrate-only") => {
+            let pool = configuration.database.get_connection_pool();
+            run_migrations(&pool)
+                .await
+                .context("Unable to migrate the database")?;
+            tracing::info!("The database schema is up to date");
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let application = Application::build(configuration.clone(), log_filter).await?;
+    let settings_reloader = application.settings_reloader();
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(
+        configuration,
+        settings_reloader.email_client(),
+        settings_reloader.confirmation_links(),
+    ));
+    let reload_task = tokio::spawn(settings_reloader.run_until_stopped());
+
+    tokio::select! {
+        outcome = application_task => report_exit("API", outcome),
+        outcome = worker_task => report_exit("Outbox dispatcher", outcome),
+        outcome = reload_task => report_exit("Configuration reloader", outcome),
+    }
+}
 
-    application_task.await?;
-    //tokio::spawn(application_task);
+/// `zero2prod print-config` shows the configuration this deployment resolves to,
+/// and exits with a non-zero status if it is invalid.
+fn print_configuration() -> anyhow::Result<()> {
+    print!("{}", describe_configuration()?);
+    get_configuration()?;
     Ok(())
 }
+
+/// `zero2prod issue-api-key <username> [scope...]` prints a new API key for an
+/// existing user, so the first key can be created without calling the API.
+/// Without any scope the key is granted every scope.
+async fn issue_bootstrap_api_key(configuration: Settings, args: &[String]) -> anyhow::Result<()> {
+    let (username, scopes) = args
+        .split_first()
+        .context("Usage: zero2prod issue-api-key <username> [scope...]")?;
+    let scopes = if scopes.is_empty() {
+        Scope::ALL.to_vec()
+    } else {
+        scopes
+            .iter()
+            .map(|s| Scope::try_from(s.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(anyhow::Error::msg)?
+    };
+
+    let pool = configuration.database.get_connection_pool();
+    let user_id = sqlx::query!(r#"select user_id from users where username = $1"#, username)
+        .fetch_optional(&pool)
+        .await?
+        .with_context(|| format!("There is no user called {}", username))?
+        .user_id;
+
+    let key = NewApiKey::generate();
+    issue_api_key(
+        &pool,
+        &configuration.application.hmac_secret,
+        user_id,
+        "bootstrap",
+        &scopes,
+        None,
+        &key,
+    )
+    .await?;
+    println!("{}", key.expose());
+
+    Ok(())
+}
+
+/// Logs how the first task to stop ended. A failure is passed on, so the process
+/// exits with a non-zero status and gets restarted.
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), impl Debug + Display>, JoinError>,
+) -> anyhow::Result<()> {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name);
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            );
+            Err(anyhow::anyhow!("{} failed: {}", task_name, e))
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            );
+            Err(anyhow::anyhow!(
+                "{} task failed to complete: {}",
+                task_name,
+                e
+            ))
+        }
+    }
+}
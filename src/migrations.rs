@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use sqlx::migrate::{AppliedMigration, Migrate, MigrateError, Migration, Migrator};
+use sqlx::PgPool;
+
+/// The contents of `migrations/`, built into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[tracing::instrument(name = "Apply pending migrations", skip(pool))]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), MigrateError> {
+    MIGRATOR.run(pool).await
+}
+
+/// Fails unless the database has exactly the migrations this binary was built with.
+#[tracing::instrument(name = "Check the database schema", skip(pool))]
+pub async fn check_schema(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut connection = pool.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    if let Some(version) = connection.dirty_version().await? {
+        anyhow::bail!("Migration {} was only partially applied", version);
+    }
+    let applied = connection.list_applied_migrations().await?;
+
+    let mismatch = SchemaMismatch::between(&MIGRATOR.migrations, &applied);
+    if mismatch.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "The database schema does not match this build: {}",
+            mismatch
+        ))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SchemaMismatch {
+    /// Known to the binary, but not applied yet.
+    pending: Vec<i64>,
+    /// Applied by a newer build.
+    unknown: Vec<i64>,
+    /// Applied, but edited since.
+    modified: Vec<i64>,
+}
+
+impl SchemaMismatch {
+    fn between(migrations: &[Migration], applied: &[AppliedMigration]) -> SchemaMismatch {
+        let applied: HashMap<i64, &[u8]> = applied
+            .iter()
+            .map(|migration| (migration.version, migration.checksum.as_ref()))
+            .collect();
+        let migrations: Vec<&Migration> = migrations
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .collect();
+
+        let mut mismatch = SchemaMismatch::default();
+        for migration in &migrations {
+            match applied.get(&migration.version) {
+                None => mismatch.pending.push(migration.version),
+                Some(checksum) if *checksum != migration.checksum.as_ref() => {
+                    mismatch.modified.push(migration.version)
+                }
+                Some(_) => {}
+            }
+        }
+        mismatch.unknown = applied
+            .keys()
+            .filter(|version| !migrations.iter().any(|m| m.version == **version))
+            .copied()
+            .collect();
+        mismatch.unknown.sort_unstable();
+        mismatch
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.unknown.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problems: Vec<String> = [
+            ("pending", &self.pending),
+            ("unknown to this build", &self.unknown),
+            ("modified since they were applied", &self.modified),
+        ]
+        .into_iter()
+        .filter(|(_, versions)| !versions.is_empty())
+        .map(|(problem, versions)| format!("{}: {:?}", problem, versions))
+        .collect();
+        write!(f, "{}", problems.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use sqlx::migrate::MigrationType;
+
+    use super::*;
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(
+            version,
+            Cow::Borrowed("test"),
+            MigrationType::Simple,
+            Cow::Borrowed(sql),
+        )
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            checksum: migration.checksum.clone(),
+        }
+    }
+
+    #[test]
+    fn an_up_to_date_schema_matches() {
+        let migrations = [
+            migration(1, "create table a();"),
+            migration(2, "create table b();"),
+        ];
+        let applied: Vec<_> = migrations.iter().map(applied).collect();
+
+        assert!(SchemaMismatch::between(&migrations, &applied).is_empty());
+    }
+
+    #[test]
+    fn a_schema_behind_the_binary_has_pending_migrations() {
+        let migrations = [
+            migration(1, "create table a();"),
+            migration(2, "create table b();"),
+        ];
+        let applied = [applied(&migrations[0])];
+
+        let mismatch = SchemaMismatch::between(&migrations, &applied);
+
+        assert_eq!(mismatch.pending, vec![2]);
+        assert!(mismatch.unknown.is_empty());
+    }
+
+    #[test]
+    fn a_schema_ahead_of_the_binary_has_unknown_migrations() {
+        let migrations = [migration(1, "create table a();")];
+        let applied = [
+            applied(&migrations[0]),
+            applied(&migration(2, "create table b();")),
+        ];
+
+        let mismatch = SchemaMismatch::between(&migrations, &applied);
+
+        assert_eq!(mismatch.unknown, vec![2]);
+        assert!(mismatch.pending.is_empty());
+    }
+
+    #[test]
+    fn edited_migrations_are_reported() {
+        let migrations = [migration(1, "create table a();")];
+        let applied = [applied(&migration(1, "create table a(id int);"))];
+
+        let mismatch = SchemaMismatch::between(&migrations, &applied);
+
+        assert_eq!(mismatch.modified, vec![1]);
+        assert_eq!(
+            mismatch.to_string(),
+            "modified since they were applied: [1]"
+        );
+    }
+}
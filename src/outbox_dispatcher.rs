@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::{ConfirmationLinkSettings, Settings};
+use crate::confirmation_email::{ConfirmationEmail, ConfirmationEmailRenderer};
+use crate::delivery_log::{record_deliveries, DeliveryStatus, NewDelivery, CONFIRMATION_TEMPLATE};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::reload::Reloadable;
+
+/// Emails that still can't be delivered after this many attempts are no longer
+/// retried. They are kept with their last error until `ABANDONED_EMAIL_RETENTION`
+/// has passed.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+pub const ABANDONED_EMAIL_RETENTION: chrono::Duration = chrono::Duration::days(14);
+
+/// The bodies are rendered from `template` when the email is sent, so secrets
+/// such as subscription tokens are never stored in the outbox.
+pub struct OutboxEmail<'a> {
+    pub recipient: &'a str,
+    pub template: &'a str,
+    pub subject: &'a str,
+    pub subscriber_id: Uuid,
+}
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Queues an email for delivery by the dispatcher. It is written as part of
+/// `transaction`, so it is only sent if the surrounding changes are committed.
+#[tracing::instrument(
+    name = "Add an email to the outbox",
+    skip(transaction, email),
+    fields(template = %email.template)
+)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: OutboxEmail<'_>,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox
+            (id, recipient, template, subject, subscriber_id, created_at, next_attempt_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        "#,
+        Uuid::new_v4(),
+        email.recipient,
+        email.template,
+        email.subject,
+        email.subscriber_id,
+        now,
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+/// Sends with whichever email client `email_client` holds, and renders with
+/// whichever `confirmation_links` settings are current, when each email is picked up.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: Reloadable<EmailClient>,
+    confirmation_links: Reloadable<ConfirmationLinkSettings>,
+) -> Result<(), anyhow::Error> {
+    let pool = configuration.database.get_connection_pool();
+    let renderer = ConfirmationEmailRenderer::new(
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+        confirmation_links,
+    );
+    worker_loop(pool, email_client, renderer).await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Reloadable<EmailClient>,
+    renderer: ConfirmationEmailRenderer,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&pool, &email_client.current(), &renderer).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                let _ = purge_abandoned_emails(&pool).await;
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Dispatch an email from the outbox",
+    skip_all,
+    fields(email_id = tracing::field::Empty, template = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    renderer: &ConfirmationEmailRenderer,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query!(
+        r#"
+        SELECT id, recipient, template, subject, subscriber_id, attempts
+        FROM email_outbox
+        WHERE attempts < $1 AND next_attempt_at <= now()
+        ORDER BY next_attempt_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        MAX_ATTEMPTS,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let task = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current()
+        .record("email_id", display(task.id))
+        .record("template", display(&task.template));
+
+    let recipient = match SubscriberEmail::parse(task.recipient.clone()) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::error!(
+                "Giving up on an outbox email with an invalid recipient: {}",
+                e
+            );
+            give_up(&mut transaction, task.id, &e).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let rendered = match task.template.as_str() {
+        CONFIRMATION_TEMPLATE => renderer.render(task.subscriber_id),
+        template => Err(format!("There is no template called {}", template)),
+    };
+    let email = match rendered {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                "Giving up on an outbox email that cannot be rendered: {}",
+                e
+            );
+            give_up(&mut transaction, task.id, &e).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let outcome = email_client
+        .send_email(recipient, &task.subject, &email.html_body, &email.text_body)
+        .await;
+
+    let delivery = match &outcome {
+        Ok(response) => {
+            store_sent_token(&mut transaction, task.subscriber_id, &email).await?;
+            delete_sent(&mut transaction, task.id).await?;
+            NewDelivery {
+                recipient: &task.recipient,
+                template: &task.template,
+                subject: &task.subject,
+                provider_message_id: response.message_id.as_deref(),
+                status: DeliveryStatus::Sent,
+                error: None,
+            }
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Failed to deliver an outbox email on attempt {}: {:?}",
+                task.attempts + 1,
+                e
+            );
+            schedule_retry(&mut transaction, task.id, task.attempts, &e.to_string()).await?;
+            NewDelivery {
+                recipient: &task.recipient,
+                template: &task.template,
+                subject: &task.subject,
+                provider_message_id: None,
+                status: DeliveryStatus::Failed,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    transaction.commit().await?;
+
+    if let Err(e) = record_deliveries(pool, &[delivery]).await {
+        tracing::error!("Failed to record the outbox email delivery: {:?}", e);
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Only the token in the email that was actually sent can confirm the
+/// subscription. Links rendered for failed attempts never reached anyone.
+async fn store_sent_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email: &ConfirmationEmail,
+) -> Result<(), sqlx::Error> {
+    if let Some(token_hash) = &email.token_hash {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscription_tokens (subscription_token_hash, subscriber_id) VALUES ($1, $2)
+            "#,
+            token_hash,
+            subscriber_id
+        )
+        .execute(transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {}", e);
+            e
+        })?;
+    }
+    Ok(())
+}
+
+/// The delivery log keeps the record of the send, so the outbox doesn't hold on to
+/// the recipient once it is no longer needed.
+async fn delete_sent(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(r#"DELETE FROM email_outbox WHERE id = $1"#, email_id)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
+
+/// Deletes emails that ran out of attempts more than `ABANDONED_EMAIL_RETENTION` ago.
+#[tracing::instrument(name = "Purge abandoned outbox emails", skip(pool), err)]
+pub async fn purge_abandoned_emails(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM email_outbox WHERE attempts >= $1 AND created_at < $2"#,
+        MAX_ATTEMPTS,
+        Utc::now() - ABANDONED_EMAIL_RETENTION,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+async fn schedule_retry(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+    attempts: i32,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET attempts = attempts + 1, next_attempt_at = $2, last_error = $3
+        WHERE id = $1
+        "#,
+        email_id,
+        Utc::now() + retry_delay(attempts),
+        error
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn give_up(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE email_outbox SET attempts = $2, last_error = $3 WHERE id = $1"#,
+        email_id,
+        MAX_ATTEMPTS,
+        error
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Exponential backoff between attempts: 30s, 1m, 2m, 4m, ...
+fn retry_delay(attempts: i32) -> chrono::Duration {
+    chrono::Duration::seconds(30 * 2_i64.pow(attempts.clamp(0, 10) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_with_every_attempt() {
+        assert_eq!(retry_delay(0), chrono::Duration::seconds(30));
+        assert_eq!(retry_delay(1), chrono::Duration::seconds(60));
+        assert_eq!(retry_delay(3), chrono::Duration::seconds(240));
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert_eq!(retry_delay(50), retry_delay(10));
+    }
+}
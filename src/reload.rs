@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing_subscriber::EnvFilter;
+
+use crate::configuration::{
+    configuration_files, get_configuration, ConfirmationLinkSettings, Settings,
+};
+use crate::email_client::EmailClient;
+use crate::telemetry::LogFilter;
+use crate::tls::{load_certified_key, CertificateResolver};
+
+/// How often the configuration files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A value that can be swapped out while the application is running. Readers
+/// keep the snapshot they took until they are done with it.
+pub struct Reloadable<T>(Arc<ArcSwap<T>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Reloadable<T> {
+        Reloadable(Arc::new(ArcSwap::from_pointee(value)))
+    }
+
+    pub fn current(&self) -> Arc<T> {
+        self.0.load_full()
+    }
+
+    pub fn replace(&self, value: T) {
+        self.0.store(Arc::new(value))
+    }
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Reloadable(self.0.clone())
+    }
+}
+
+/// Applies the settings that can change without a restart: `email_client`,
+/// `confirmation_links`, `telemetry.log.filter` and the TLS certificate.
+#[derive(Clone)]
+pub struct SettingsReloader {
+    email_client: Reloadable<EmailClient>,
+    confirmation_links: Reloadable<ConfirmationLinkSettings>,
+    log_filter: LogFilter,
+    /// The filter of the last configuration applied. A filter set through
+    /// `/admin/log_filter` is only overwritten once the configured one changes.
+    configured_log_filter: Reloadable<String>,
+    /// Only set when the application serves HTTPS. Turning TLS on or off needs a restart.
+    certificates: Option<Arc<CertificateResolver>>,
+    certificate_files: Vec<PathBuf>,
+}
+
+impl SettingsReloader {
+    pub fn new(
+        settings: &Settings,
+        log_filter: LogFilter,
+        certificates: Option<Arc<CertificateResolver>>,
+    ) -> Result<SettingsReloader, String> {
+        let email_client = settings.email_client.clone().email_client()?;
+        let certificate_files = match &settings.application.tls {
+            Some(tls) => vec![
+                PathBuf::from(&tls.certificate_path),
+                PathBuf::from(&tls.private_key_path),
+            ],
+            None => vec![],
+        };
+        Ok(SettingsReloader {
+            email_client: Reloadable::new(email_client),
+            confirmation_links: Reloadable::new(settings.confirmation_links.clone()),
+            log_filter,
+            configured_log_filter: Reloadable::new(settings.telemetry.log.filter.clone()),
+            certificates,
+            certificate_files,
+        })
+    }
+
+    pub fn email_client(&self) -> Reloadable<EmailClient> {
+        self.email_client.clone()
+    }
+
+    pub fn confirmation_links(&self) -> Reloadable<ConfirmationLinkSettings> {
+        self.confirmation_links.clone()
+    }
+
+    pub fn log_filter(&self) -> LogFilter {
+        self.log_filter.clone()
+    }
+
+    pub fn certificates(&self) -> Option<Arc<CertificateResolver>> {
+        self.certificates.clone()
+    }
+
+    /// Either every reloadable setting is replaced, or none is.
+    pub fn apply(&self, settings: &Settings) -> Result<(), Vec<String>> {
+        settings.validate()?;
+        let email_client = settings
+            .email_client
+            .clone()
+            .email_client()
+            .map_err(|e| vec![e])?;
+        let certified_key = match (&self.certificates, &settings.application.tls) {
+            (Some(_), Some(tls)) => {
+                Some(load_certified_key(tls).map_err(|e| vec![format!("{:#}", e)])?)
+            }
+            _ => None,
+        };
+
+        let filter = &settings.telemetry.log.filter;
+        // As at startup, `RUST_LOG` takes precedence over the configured filter.
+        if *self.configured_log_filter.current() != *filter && std::env::var("RUST_LOG").is_err() {
+            let env_filter = EnvFilter::try_new(filter).map_err(|e| vec![e.to_string()])?;
+            self.log_filter
+                .replace(env_filter)
+                .map_err(|e| vec![format!("Failed to replace the log filter: {}", e)])?;
+            self.configured_log_filter.replace(filter.clone());
+        }
+        self.email_client.replace(email_client);
+        self.confirmation_links
+            .replace(settings.confirmation_links.clone());
+        if let (Some(certificates), Some(certified_key)) = (&self.certificates, certified_key) {
+            certificates.replace(certified_key);
+        }
+        Ok(())
+    }
+
+    /// Reloads the configuration whenever the process receives SIGHUP, or one of
+    /// the configuration files or the certificate changes. Files named by
+    /// `<name>_file` settings are not watched, send SIGHUP after rotating a secret.
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut files = configuration_files()?;
+        files.extend(self.certificate_files.iter().cloned());
+        let mut modified = modification_times(&files);
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {}
+                _ = interval.tick() => {
+                    let latest = modification_times(&files);
+                    if latest == modified {
+                        continue;
+                    }
+                    modified = latest;
+                }
+            }
+            self.reload();
+        }
+    }
+
+    #[tracing::instrument(name = "Reload the configuration", skip(self))]
+    fn reload(&self) {
+        let outcome = get_configuration()
+            .map_err(|e| vec![e.to_string()])
+            .and_then(|settings| self.apply(&settings));
+        match outcome {
+            Ok(()) => tracing::info!("Applied the new configuration"),
+            Err(problems) => tracing::error!(
+                "Keeping the current configuration, the new one was rejected: {}",
+                problems.join("; ")
+            ),
+        }
+    }
+}
+
+/// `None` for files that are missing, so creating or removing one counts as a change.
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replacements_are_seen_through_every_clone() {
+        let reloadable = Reloadable::new(1);
+        let clone = reloadable.clone();
+        let snapshot = reloadable.current();
+
+        clone.replace(2);
+
+        assert_eq!(*reloadable.current(), 2);
+        assert_eq!(*snapshot, 1);
+    }
+}
@@ -0,0 +1,157 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{
+    issue_api_key, list_api_keys, revoke_api_key, Authorized, ManageApiKeys, NewApiKey, Scope,
+};
+use crate::startup::HmacSecret;
+
+#[derive(Deserialize)]
+pub struct NewApiKeyData {
+    name: String,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct IssuedApiKey {
+    id: Uuid,
+    key: String,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(
+    name = "Create an API key",
+    skip(body, pool, hmac_secret, caller),
+    fields(user_id = %caller.user_id, name = %body.name)
+)]
+pub async fn create_api_key(
+    body: web::Json<NewApiKeyData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    caller: Authorized<ManageApiKeys>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    if body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+    if matches!(body.expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+        return HttpResponse::BadRequest().finish();
+    }
+    let scopes = match body
+        .scopes
+        .iter()
+        .map(|s| Scope::try_from(s.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(scopes) => scopes,
+        Err(e) => {
+            tracing::warn!("Rejecting the API key request: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    // A key can never grant more than the key used to create it.
+    if !scopes.iter().all(|scope| caller.has_scope(*scope)) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let key = NewApiKey::generate();
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let id = match issue_api_key(
+        &mut transaction,
+        &hmac_secret.0,
+        caller.user_id,
+        &body.name,
+        &scopes,
+        body.expires_at,
+        &key,
+    )
+    .await
+    {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    let diff = serde_json::json!({
+        "name": body.name,
+        "scopes": scopes,
+        "expires_at": body.expires_at,
+    });
+    if record_audit_event(
+        &mut transaction,
+        caller.audit_context(),
+        AuditAction::ApiKeyCreated,
+        Some(&id.to_string()),
+        diff,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Created().json(IssuedApiKey {
+        id,
+        key: key.expose().to_string(),
+        scopes,
+        expires_at: body.expires_at,
+    })
+}
+
+#[tracing::instrument(name = "List API keys", skip(pool, caller), fields(user_id = %caller.user_id))]
+pub async fn get_api_keys(
+    pool: web::Data<PgPool>,
+    caller: Authorized<ManageApiKeys>,
+) -> HttpResponse {
+    match list_api_keys(&pool, caller.user_id).await {
+        Ok(keys) => HttpResponse::Ok().json(keys),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Revoke an API key", skip(pool, caller), fields(user_id = %caller.user_id))]
+pub async fn delete_api_key(
+    key_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    caller: Authorized<ManageApiKeys>,
+) -> HttpResponse {
+    let key_id = key_id.into_inner();
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match revoke_api_key(&mut transaction, caller.user_id, key_id).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    let diff = serde_json::json!({ "revoked": { "from": false, "to": true } });
+    if record_audit_event(
+        &mut transaction,
+        caller.audit_context(),
+        AuditAction::ApiKeyRevoked,
+        Some(&key_id.to_string()),
+        diff,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::NoContent().finish()
+}
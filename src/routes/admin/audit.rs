@@ -0,0 +1,104 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{Authorized, ViewAuditLog};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// Events are listed newest first. `before` is the `next_cursor` of the previous page.
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    actor_id: Option<Uuid>,
+    action: Option<String>,
+    target: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct AuditEvent {
+    id: i64,
+    occurred_at: DateTime<Utc>,
+    actor_id: Option<Uuid>,
+    api_key_id: Option<Uuid>,
+    action: String,
+    target: Option<String>,
+    request_id: Option<Uuid>,
+    ip: Option<String>,
+    diff: serde_json::Value,
+}
+
+#[derive(Serialize)]
+pub struct AuditPage {
+    events: Vec<AuditEvent>,
+    next_cursor: Option<i64>,
+}
+
+#[tracing::instrument(name = "Look up audit events", skip(pool, query, _caller))]
+pub async fn get_audit_events(
+    pool: web::Data<PgPool>,
+    query: web::Query<AuditQuery>,
+    _caller: Authorized<ViewAuditLog>,
+) -> HttpResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match find_audit_events(&pool, &query, limit + 1).await {
+        Ok(mut events) => {
+            let next_cursor = if events.len() as i64 > limit {
+                events.truncate(limit as usize);
+                events.last().map(|e| e.id)
+            } else {
+                None
+            };
+            HttpResponse::Ok().json(AuditPage {
+                events,
+                next_cursor,
+            })
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn find_audit_events(
+    pool: &PgPool,
+    query: &AuditQuery,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditEvent,
+        r#"
+        select id, occurred_at, actor_id, api_key_id, action, target, request_id, ip, diff
+        from audit_events
+        where ($1::uuid is null or actor_id = $1)
+            and ($2::text is null or action = $2)
+            and ($3::text is null or target = $3)
+            and ($4::timestamptz is null or occurred_at >= $4)
+            and ($5::timestamptz is null or occurred_at < $5)
+            and ($6::bigint is null or id < $6)
+        order by id desc
+        limit $7
+        "#,
+        query.actor_id,
+        query.action,
+        query.target,
+        query.from,
+        query.to,
+        query.before,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
@@ -0,0 +1,58 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{Authorized, ViewDeliveries};
+
+#[derive(Deserialize)]
+pub struct DeliveryQuery {
+    email: String,
+}
+
+#[derive(Serialize)]
+pub struct Delivery {
+    id: Uuid,
+    recipient: String,
+    template: String,
+    subject: String,
+    provider_message_id: Option<String>,
+    status: String,
+    error: Option<String>,
+    attempted_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Look up email deliveries for a recipient", skip(pool, query))]
+pub async fn get_deliveries(
+    pool: web::Data<PgPool>,
+    query: web::Query<DeliveryQuery>,
+    _: Authorized<ViewDeliveries>,
+) -> HttpResponse {
+    match get_deliveries_for_recipient(&pool, &query.email).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+async fn get_deliveries_for_recipient(
+    pool: &PgPool,
+    recipient: &str,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        select id, recipient, template, subject, provider_message_id, status, error, attempted_at
+        from email_deliveries
+        where lower(recipient) = lower($1)
+        order by attempted_at desc
+        "#,
+        recipient
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
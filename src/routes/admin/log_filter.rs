@@ -0,0 +1,77 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing_subscriber::EnvFilter;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{Authorized, ManageLogging};
+use crate::telemetry::LogFilter;
+
+/// `filter` takes `EnvFilter` directives, e.g. `info,zero2prod=debug,sqlx=warn`.
+#[derive(Deserialize, Serialize)]
+pub struct LogFilterData {
+    filter: String,
+}
+
+#[tracing::instrument(name = "Get the log filter", skip(log_filter, _caller))]
+pub async fn get_log_filter(
+    log_filter: web::Data<LogFilter>,
+    _caller: Authorized<ManageLogging>,
+) -> HttpResponse {
+    match log_filter.current() {
+        Ok(filter) => HttpResponse::Ok().json(LogFilterData { filter }),
+        Err(e) => {
+            tracing::error!("Failed to read the log filter: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The change only lasts until the next restart.
+#[tracing::instrument(
+    name = "Change the log filter",
+    skip(body, pool, log_filter, caller),
+    fields(user_id = %caller.user_id, filter = %body.filter)
+)]
+pub async fn update_log_filter(
+    body: web::Json<LogFilterData>,
+    pool: web::Data<PgPool>,
+    log_filter: web::Data<LogFilter>,
+    caller: Authorized<ManageLogging>,
+) -> HttpResponse {
+    let filter = match EnvFilter::try_new(&body.filter) {
+        Ok(filter) => filter,
+        Err(e) => {
+            tracing::warn!("Rejecting the log filter: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let previous = match log_filter.current() {
+        Ok(previous) => previous,
+        Err(e) => {
+            tracing::error!("Failed to read the log filter: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let current = filter.to_string();
+
+    let diff = serde_json::json!({ "filter": { "from": previous, "to": current } });
+    if record_audit_event(
+        pool.get_ref(),
+        caller.audit_context(),
+        AuditAction::LogFilterChanged,
+        None,
+        diff,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Err(e) = log_filter.replace(filter) {
+        tracing::error!("Failed to replace the log filter: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(LogFilterData { filter: current })
+}
@@ -0,0 +1,13 @@
+mod api_keys;
+mod audit;
+mod deliveries;
+mod log_filter;
+mod subscribers;
+mod users;
+
+pub use api_keys::*;
+pub use audit::*;
+pub use deliveries::*;
+pub use log_filter::*;
+pub use subscribers::*;
+pub use users::*;
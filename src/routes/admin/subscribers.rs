@@ -0,0 +1,359 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{Authorized, ViewSubscribers};
+use crate::domain::{SubscriberEmail, SubscriberName, SubscriptionStatus};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    SubscribedAt,
+    Email,
+    Name,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct SubscriberQuery {
+    status: Option<SubscriptionStatus>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the name or email.
+    search: Option<String>,
+    #[serde(default)]
+    sort: SortField,
+    #[serde(default)]
+    order: SortOrder,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+impl SubscriberQuery {
+    /// The names of the filters in use. Their values are left out: a search
+    /// term is usually part of someone's name or email address.
+    fn filters(&self) -> Vec<&'static str> {
+        [
+            ("status", self.status.is_some()),
+            ("subscribed_after", self.subscribed_after.is_some()),
+            ("subscribed_before", self.subscribed_before.is_some()),
+            ("search", self.search.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, used)| used.then_some(name))
+        .collect()
+    }
+}
+
+/// Where the previous page stopped: the sort key and ID of its last subscriber.
+/// Handed out hex-encoded so clients treat it as opaque.
+#[derive(Deserialize, Serialize, Debug)]
+struct Cursor {
+    sort: SortField,
+    order: SortOrder,
+    value: String,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("A cursor can always be serialised"))
+    }
+
+    fn decode(s: &str) -> Result<Cursor, String> {
+        let bytes = hex::decode(s).map_err(|_| format!("{} is not a valid cursor", s))?;
+        serde_json::from_slice(&bytes).map_err(|_| format!("{} is not a valid cursor", s))
+    }
+}
+
+struct Subscriber {
+    id: Uuid,
+    email: SubscriberEmail,
+    name: SubscriberName,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    signup_origin: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    signup_origin: Option<String>,
+}
+
+impl TryFrom<SubscriberRow> for Subscriber {
+    type Error = String;
+
+    fn try_from(row: SubscriberRow) -> Result<Self, Self::Error> {
+        Ok(Subscriber {
+            id: row.id,
+            email: SubscriberEmail::parse(row.email)?,
+            name: SubscriberName::parse(row.name)?,
+            status: row.status,
+            subscribed_at: row.subscribed_at,
+            signup_origin: row.signup_origin,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubscriberDto {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    signup_origin: Option<String>,
+}
+
+impl From<Subscriber> for SubscriberDto {
+    fn from(subscriber: Subscriber) -> Self {
+        SubscriberDto {
+            id: subscriber.id,
+            email: subscriber.email.as_ref().to_string(),
+            name: subscriber.name.as_ref().to_string(),
+            status: subscriber.status,
+            subscribed_at: subscriber.subscribed_at,
+            signup_origin: subscriber.signup_origin,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<SubscriberDto>,
+    total: i64,
+    next_cursor: Option<String>,
+}
+
+#[tracing::instrument(name = "List subscribers", skip(pool, query, caller))]
+pub async fn get_subscribers(
+    pool: web::Data<PgPool>,
+    query: web::Query<SubscriberQuery>,
+    caller: Authorized<ViewSubscribers>,
+) -> HttpResponse {
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return HttpResponse::BadRequest().finish();
+    }
+    let cursor = match query.cursor.as_deref().map(Cursor::decode).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            tracing::warn!("Rejecting the subscriber listing: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    // A cursor only makes sense for the ordering it was produced with.
+    if matches!(&cursor, Some(c) if c.sort != query.sort || c.order != query.order) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let total = match count_subscribers(&pool, &query).await {
+        Ok(total) => total,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let mut rows = match find_subscribers(&pool, &query, cursor.as_ref(), limit + 1).await {
+        Ok(rows) => rows,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let next_cursor = if has_more {
+        rows.last().map(|r| {
+            let value = match query.sort {
+                SortField::SubscribedAt => r.subscribed_at.to_rfc3339(),
+                SortField::Email => r.email.clone(),
+                SortField::Name => r.name.clone(),
+            };
+            Cursor {
+                sort: query.sort,
+                order: query.order,
+                value,
+                id: r.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    let mut subscribers = Vec::with_capacity(rows.len());
+    for row in rows {
+        match Subscriber::try_from(row) {
+            Ok(subscriber) => subscribers.push(SubscriberDto::from(subscriber)),
+            Err(e) => tracing::warn!(
+                "Skipping a subscriber. Their stored contact details are invalid: {}",
+                e
+            ),
+        }
+    }
+
+    // Subscriber details are personal data, so every listing leaves a trace.
+    let diff = serde_json::json!({
+        "filters": query.filters(),
+        "sort": query.sort,
+        "order": query.order,
+    });
+    if record_audit_event(
+        pool.get_ref(),
+        caller.audit_context(),
+        AuditAction::SubscribersListed,
+        None,
+        diff,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        total,
+        next_cursor,
+    })
+}
+
+fn push_filters(builder: &mut QueryBuilder<'_, Postgres>, query: &SubscriberQuery) {
+    builder.push(" where true");
+    if let Some(status) = query.status {
+        builder.push(" and status = ").push_bind(status);
+    }
+    if let Some(after) = query.subscribed_after {
+        builder.push(" and subscribed_at >= ").push_bind(after);
+    }
+    if let Some(before) = query.subscribed_before {
+        builder.push(" and subscribed_at < ").push_bind(before);
+    }
+    if let Some(search) = query.search.as_deref().map(str::trim) {
+        if !search.is_empty() {
+            let pattern = format!("%{}%", escape_like(search));
+            builder
+                .push(" and (name ilike ")
+                .push_bind(pattern.clone())
+                .push(" or email ilike ")
+                .push_bind(pattern)
+                .push(")");
+        }
+    }
+}
+
+/// Makes `%`, `_` and `\` match themselves in an `ilike` pattern.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+async fn count_subscribers(pool: &PgPool, query: &SubscriberQuery) -> Result<i64, sqlx::Error> {
+    let mut builder = QueryBuilder::new("select count(*) from subscriptions");
+    push_filters(&mut builder, query);
+    let (total,): (i64,) = builder
+        .build_query_as()
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+    Ok(total)
+}
+
+async fn find_subscribers(
+    pool: &PgPool,
+    query: &SubscriberQuery,
+    cursor: Option<&Cursor>,
+    limit: i64,
+) -> Result<Vec<SubscriberRow>, sqlx::Error> {
+    let column = match query.sort {
+        SortField::SubscribedAt => "subscribed_at",
+        SortField::Email => "email",
+        SortField::Name => "name",
+    };
+    let (comparison, direction) = match query.order {
+        SortOrder::Asc => (">", "asc"),
+        SortOrder::Desc => ("<", "desc"),
+    };
+
+    let mut builder = QueryBuilder::new(
+        "select id, email, name, status, subscribed_at, signup_origin from subscriptions",
+    );
+    push_filters(&mut builder, query);
+    if let Some(cursor) = cursor {
+        builder.push(format!(" and ({}, id) {} (", column, comparison));
+        match query.sort {
+            SortField::SubscribedAt => builder
+                .push_bind(cursor.value.clone())
+                .push("::timestamptz"),
+            SortField::Email | SortField::Name => builder.push_bind(cursor.value.clone()),
+        };
+        builder.push(", ").push_bind(cursor.id).push(")");
+    }
+    builder
+        .push(format!(
+            " order by {} {}, id {}",
+            column, direction, direction
+        ))
+        .push(" limit ")
+        .push_bind(limit);
+
+    builder.build_query_as().fetch_all(pool).await.map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_err;
+
+    use super::*;
+
+    #[test]
+    fn a_cursor_survives_a_round_trip() {
+        let cursor = Cursor {
+            sort: SortField::Email,
+            order: SortOrder::Asc,
+            value: "ursula@example.com".to_string(),
+            id: Uuid::new_v4(),
+        };
+
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+
+        assert_eq!(decoded.sort, cursor.sort);
+        assert_eq!(decoded.order, cursor.order);
+        assert_eq!(decoded.value, cursor.value);
+        assert_eq!(decoded.id, cursor.id);
+    }
+
+    #[test]
+    fn a_tampered_cursor_is_rejected() {
+        assert_err!(Cursor::decode("not-hex"));
+        assert_err!(Cursor::decode(&hex::encode("{}")));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"100%_a\b"), r"100\%\_a\\b");
+    }
+}
@@ -0,0 +1,188 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{Authorized, ManageUsers, Role};
+
+#[derive(Deserialize)]
+pub struct NewUserData {
+    username: String,
+    role: String,
+}
+
+#[derive(Deserialize)]
+pub struct RoleData {
+    role: String,
+}
+
+#[derive(Serialize)]
+pub struct User {
+    user_id: Uuid,
+    username: String,
+    role: String,
+}
+
+#[tracing::instrument(
+    name = "Create an admin user",
+    skip(body, pool, caller),
+    fields(user_id = %caller.user_id, username = %body.username)
+)]
+pub async fn create_user(
+    body: web::Json<NewUserData>,
+    pool: web::Data<PgPool>,
+    caller: Authorized<ManageUsers>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let role = match Role::try_from(body.role.as_str()) {
+        Ok(role) => role,
+        Err(e) => {
+            tracing::warn!("Rejecting the new user: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let username = body.username.trim();
+    if username.is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let user = match insert_user(&mut transaction, username, role).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Conflict().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let diff = serde_json::json!({ "username": user.username, "role": user.role });
+    if record_audit_event(
+        &mut transaction,
+        caller.audit_context(),
+        AuditAction::UserCreated,
+        Some(&user.user_id.to_string()),
+        diff,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Created().json(user)
+}
+
+#[tracing::instrument(
+    name = "Change the role of an admin user",
+    skip(body, pool, caller),
+    fields(caller_id = %caller.user_id, role = %body.role)
+)]
+pub async fn update_user_role(
+    user_id: web::Path<Uuid>,
+    body: web::Json<RoleData>,
+    pool: web::Data<PgPool>,
+    caller: Authorized<ManageUsers>,
+) -> HttpResponse {
+    let user_id = user_id.into_inner();
+    let role = match Role::try_from(body.role.as_str()) {
+        Ok(role) => role,
+        Err(e) => {
+            tracing::warn!("Rejecting the role change: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    // Owners cannot demote themselves, so there is always someone left to manage users.
+    if user_id == caller.user_id {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let (user, previous_role) = match set_role(&mut transaction, user_id, role).await {
+        Ok(Some(changed)) => changed,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let diff = serde_json::json!({ "role": { "from": previous_role, "to": user.role } });
+    if record_audit_event(
+        &mut transaction,
+        caller.audit_context(),
+        AuditAction::UserRoleChanged,
+        Some(&user_id.to_string()),
+        diff,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(user)
+}
+
+/// Returns `None` if the username is already taken.
+async fn insert_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    role: Role,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as!(
+        User,
+        r#"
+        insert into users (user_id, username, role) values ($1, $2, $3)
+        on conflict (username) do nothing
+        returning user_id, username, role
+        "#,
+        Uuid::new_v4(),
+        username,
+        role.as_str()
+    )
+    .fetch_optional(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+/// Returns the updated user together with the role they had before.
+async fn set_role(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    role: Role,
+) -> Result<Option<(User, String)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        update users set role = $2
+        from (select role from users where user_id = $1 for update) previous
+        where user_id = $1
+        returning users.user_id, users.username, users.role, previous.role as previous_role
+        "#,
+        user_id,
+        role.as_str()
+    )
+    .fetch_optional(transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(row.map(|r| {
+        let user = User {
+            user_id: r.user_id,
+            username: r.username,
+            role: r.role,
+        };
+        (user, r.previous_role)
+    }))
+}
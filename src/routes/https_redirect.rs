@@ -0,0 +1,20 @@
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::startup::ApplicationBaseUrl;
+
+/// Sends plain HTTP requests to the same path and query on the HTTPS
+/// `base_url`. 308 keeps the method and body, so forms still submit.
+pub async fn redirect_to_https(
+    request: HttpRequest,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+    HttpResponse::PermanentRedirect()
+        .insert_header((LOCATION, format!("{}{}", base_url.0, path_and_query)))
+        .finish()
+}
@@ -1,4 +1,8 @@
+pub mod admin;
 pub mod health_check;
+pub mod https_redirect;
+pub mod newsletters;
+pub mod signup_widget;
 pub mod subscriptions;
 pub mod subscriptions_confirm;
 pub use health_check::*;
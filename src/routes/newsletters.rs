@@ -0,0 +1,217 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::audit::{record_audit_event, AuditAction};
+use crate::authentication::{Authorized, PublishNewsletters};
+use crate::delivery_log::{
+    record_deliveries, DeliveryStatus, NewDelivery, NEWSLETTER_ISSUE_TEMPLATE,
+};
+use crate::domain::{
+    MergeFields, NewsletterTemplate, SubscriberEmail, SubscriberName, SubscriptionStatus,
+};
+use crate::email_client::{BatchSendError, Email, EmailClient};
+use crate::reload::Reloadable;
+
+#[derive(Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+}
+
+#[derive(Deserialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+/// How an issue fared, per recipient. Rejected messages were refused by
+/// Postmark for that recipient alone; failed ones were in a batch request that
+/// did not go through at all.
+#[derive(Serialize)]
+pub struct PublishSummary {
+    recipients: usize,
+    sent: usize,
+    rejected: usize,
+    failed: usize,
+}
+
+struct ConfirmedSubscriber {
+    email: SubscriberEmail,
+    name: SubscriberName,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, email_client, caller),
+    fields(title = %body.title, user_id = %caller.user_id)
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<Reloadable<EmailClient>>,
+    caller: Authorized<PublishNewsletters>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let html_template = match NewsletterTemplate::parse(body.content.html) {
+        Ok(template) => template,
+        Err(e) => {
+            tracing::warn!("Rejecting the html content of the newsletter: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let text_template = match NewsletterTemplate::parse(body.content.text) {
+        Ok(template) => template,
+        Err(e) => {
+            tracing::warn!("Rejecting the text content of the newsletter: {}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+
+    let subscribers = match get_confirmed_subscribers(&pool).await {
+        Ok(subscribers) => subscribers,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let mut emails = Vec::new();
+    for subscriber in subscribers {
+        match subscriber {
+            Ok(subscriber) => {
+                let fields = MergeFields {
+                    name: Some(subscriber.name.as_ref()),
+                    email: Some(subscriber.email.as_ref()),
+                    // There is no unsubscribe or archive page to link to yet, so
+                    // `unsubscribe_url` and `archive_url` render their fallback.
+                    ..MergeFields::default()
+                };
+                let html_content = html_template.render(&fields);
+                let text_content = text_template.render(&fields);
+
+                emails.push(Email {
+                    recipient: subscriber.email,
+                    subject: body.title.clone(),
+                    html_content,
+                    text_content,
+                });
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    let outcomes = email_client.current().send_email_batch(&emails).await;
+    let mut summary = PublishSummary {
+        recipients: emails.len(),
+        sent: 0,
+        rejected: 0,
+        failed: 0,
+    };
+    let mut deliveries = Vec::with_capacity(emails.len());
+    for (email, outcome) in emails.iter().zip(&outcomes) {
+        let (status, provider_message_id, error) = match outcome {
+            Ok(response) => {
+                summary.sent += 1;
+                (DeliveryStatus::Sent, response.message_id.as_deref(), None)
+            }
+            Err(BatchSendError::Rejected {
+                error_code,
+                message,
+            }) => {
+                summary.rejected += 1;
+                tracing::warn!(
+                    "Postmark rejected the newsletter issue for {} with error code {}: {}",
+                    email.recipient,
+                    error_code,
+                    message
+                );
+                (
+                    DeliveryStatus::Rejected,
+                    None,
+                    Some(format!("{}: {}", error_code, message)),
+                )
+            }
+            Err(BatchSendError::RequestFailed(e)) => {
+                summary.failed += 1;
+                (DeliveryStatus::Failed, None, Some(e.clone()))
+            }
+        };
+        deliveries.push(NewDelivery {
+            recipient: email.recipient.as_ref(),
+            template: NEWSLETTER_ISSUE_TEMPLATE,
+            subject: &body.title,
+            provider_message_id,
+            status,
+            error,
+        });
+    }
+
+    if let Err(e) = record_deliveries(&pool, &deliveries).await {
+        tracing::error!("Failed to record the newsletter issue deliveries: {:?}", e);
+    }
+
+    // The issue has gone out whether or not this succeeds, so a failure is only logged.
+    let diff = serde_json::json!({
+        "title": body.title,
+        "recipients": summary.recipients,
+        "sent": summary.sent,
+        "rejected": summary.rejected,
+        "failed": summary.failed,
+    });
+    if let Err(e) = record_audit_event(
+        pool.get_ref(),
+        caller.audit_context(),
+        AuditAction::NewsletterPublished,
+        Some(&body.title),
+        diff,
+    )
+    .await
+    {
+        tracing::error!(
+            "Failed to record the newsletter issue in the audit log: {:?}",
+            e
+        );
+    }
+
+    if summary.failed > 0 {
+        tracing::error!(
+            "Failed to deliver the newsletter issue to {} out of {} subscribers",
+            summary.failed,
+            summary.recipients
+        );
+        return HttpResponse::InternalServerError().json(summary);
+    }
+
+    HttpResponse::Ok().json(summary)
+}
+
+#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
+async fn get_confirmed_subscribers(
+    pool: &PgPool,
+) -> Result<Vec<Result<ConfirmedSubscriber, String>>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"select email, name from subscriptions where status = $1"#,
+        SubscriptionStatus::Confirmed as SubscriptionStatus
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    let confirmed_subscribers = rows
+        .into_iter()
+        .map(|r| {
+            Ok(ConfirmedSubscriber {
+                email: SubscriberEmail::parse(r.email)?,
+                name: SubscriberName::parse(r.name)?,
+            })
+        })
+        .collect();
+
+    Ok(confirmed_subscribers)
+}
@@ -0,0 +1,179 @@
+// Newsletter signup form for other sites. Include it where the form should appear:
+//
+//   <script src="https://newsletter.example.com/embed/signup.js" async></script>
+//
+// It posts to the server it was loaded from, which must list the site in
+// `application.cors_allowed_origins`.
+(function () {
+  "use strict";
+
+  var script = document.currentScript;
+  if (!script) {
+    return;
+  }
+  var endpoint = new URL("/subscribe", script.src).toString();
+  var formEndpoint = new URL("/subscribe/form", script.src).toString();
+  var challengeScripts = {
+    hcaptcha: { src: "https://js.hcaptcha.com/1/api.js?render=explicit", global: "hcaptcha" },
+    turnstile: {
+      src: "https://challenges.cloudflare.com/turnstile/v0/api.js?render=explicit",
+      global: "turnstile",
+    },
+  };
+
+  function element(tag, properties, children) {
+    var node = document.createElement(tag);
+    Object.keys(properties || {}).forEach(function (key) {
+      node[key] = properties[key];
+    });
+    (children || []).forEach(function (child) {
+      node.appendChild(child);
+    });
+    return node;
+  }
+
+  function field(name, label, type, autocomplete) {
+    var input = element("input", {
+      name: name,
+      type: type,
+      autocomplete: autocomplete,
+      required: true,
+    });
+    var error = element("p", { className: "zero2prod-signup-error", hidden: true });
+    error.style.color = "#b00020";
+    error.style.margin = "0.25em 0 0.75em";
+    var wrapper = element("div", {}, [
+      element("label", {}, [document.createTextNode(label + " "), input]),
+      error,
+    ]);
+    return { wrapper: wrapper, input: input, error: error };
+  }
+
+  var fields = {
+    name: field("name", "Name", "text", "name"),
+    email: field("email", "Email", "email", "email"),
+  };
+  // Hidden from people and assistive technology, so only bots fill it in.
+  var honeypot = element("input", {
+    name: "website",
+    type: "text",
+    tabIndex: -1,
+    autocomplete: "off",
+  });
+  var honeypotWrapper = element("div", {}, [honeypot]);
+  honeypotWrapper.setAttribute("aria-hidden", "true");
+  honeypotWrapper.style.position = "absolute";
+  honeypotWrapper.style.left = "-10000px";
+  var challengeContainer = element("div", { className: "zero2prod-signup-challenge" });
+  var button = element("button", { type: "submit", textContent: "Subscribe", disabled: true });
+  var status = element("p", { className: "zero2prod-signup-status" });
+  status.setAttribute("role", "status");
+  var form = element("form", { className: "zero2prod-signup", noValidate: true }, [
+    fields.name.wrapper,
+    fields.email.wrapper,
+    honeypotWrapper,
+    challengeContainer,
+    button,
+    status,
+  ]);
+  var formStarted = null;
+  var challenge = null;
+
+  function renderChallenge(settings) {
+    var provider = challengeScripts[settings.provider];
+    if (!provider) {
+      return Promise.resolve(null);
+    }
+    return new Promise(function (resolve, reject) {
+      var loader = element("script", { src: provider.src, async: true });
+      loader.onload = function () {
+        var api = window[provider.global];
+        var id = api.render(challengeContainer, { sitekey: settings.site_key });
+        resolve({
+          response: function () {
+            return api.getResponse(id);
+          },
+          reset: function () {
+            api.reset(id);
+          },
+        });
+      };
+      loader.onerror = reject;
+      document.head.appendChild(loader);
+    });
+  }
+
+  function showFailure() {
+    status.textContent = "Something went wrong, please try again later.";
+  }
+
+  fetch(formEndpoint)
+    .then(function (response) {
+      if (!response.ok) {
+        throw new Error("Unexpected status " + response.status);
+      }
+      return response.json();
+    })
+    .then(function (settings) {
+      formStarted = settings.form_started;
+      return settings.challenge ? renderChallenge(settings.challenge) : null;
+    })
+    .then(function (rendered) {
+      challenge = rendered;
+      button.disabled = false;
+    })
+    .catch(showFailure);
+
+  function showErrors(errors) {
+    Object.keys(fields).forEach(function (name) {
+      var message = errors[name];
+      fields[name].error.textContent = message || "";
+      fields[name].error.hidden = !message;
+      fields[name].input.setAttribute("aria-invalid", message ? "true" : "false");
+    });
+  }
+
+  form.addEventListener("submit", function (event) {
+    event.preventDefault();
+    showErrors({});
+    status.textContent = "";
+    button.disabled = true;
+
+    fetch(endpoint, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({
+        name: fields.name.input.value,
+        email: fields.email.input.value,
+        website: honeypot.value,
+        form_started: formStarted,
+        challenge_response: challenge ? challenge.response() : null,
+      }),
+    })
+      .then(function (response) {
+        if (response.ok) {
+          form.replaceChildren(
+            element("p", {
+              textContent: "Thanks! Check your inbox to confirm your subscription.",
+            })
+          );
+          return;
+        }
+        if (response.status === 400) {
+          return response.json().then(function (body) {
+            showErrors(body.errors || {});
+          });
+        }
+        if (challenge) {
+          challenge.reset();
+        }
+        throw new Error("Unexpected status " + response.status);
+      })
+      .catch(showFailure)
+      .then(function () {
+        button.disabled = false;
+      });
+  });
+
+  script.insertAdjacentElement("afterend", form);
+})();
@@ -0,0 +1,15 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::HttpResponse;
+
+const SIGNUP_WIDGET: &str = include_str!("signup_widget.js");
+
+/// A self-contained signup form other sites embed with a `<script>` tag.
+pub async fn signup_widget() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(3600),
+        ]))
+        .body(SIGNUP_WIDGET)
+}
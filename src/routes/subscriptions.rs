@@ -1,186 +1,414 @@
+use crate::bot_protection::{BotProtection, ChallengeWidget, FormToken, SignupSignals};
+use crate::confirmation_email::CONFIRMATION_SUBJECT;
+use crate::delivery_log::CONFIRMATION_TEMPLATE;
 use crate::domain::NewSubscriber;
 use crate::domain::SubscriberEmail;
 use crate::domain::SubscriberName;
-use crate::email_client;
-use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::domain::SubscriptionToken;
+use crate::outbox_dispatcher::OutboxEmail;
+use crate::redaction::{redact_email, redact_name};
+use crate::startup::HmacSecret;
+use crate::subscriber_repository::{PendingSubscriber, SubscriberRepository};
+use actix_web::http::header::{CacheControl, CacheDirective, ORIGIN};
 use actix_web::{
-    web::{self, Form},
-    HttpResponse,
+    web::{self, Either, Form, Json},
+    HttpRequest, HttpResponse,
 };
 use chrono::Utc;
-use rand::distributions::Alphanumeric;
-use rand::distributions::DistString;
-use rand::Rng;
-use serde::Deserialize;
+use reqwest::Url;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
 use sqlx::PgPool;
-use sqlx::Postgres;
-use sqlx::Transaction;
 use tracing::log;
 
 #[derive(Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    #[serde(flatten)]
+    bot_check: BotCheckFields,
+}
+
+/// Filled in by the signup form itself rather than by the subscriber.
+#[derive(Deserialize, Default)]
+struct BotCheckFields {
+    /// The honeypot. It is hidden, so people leave it empty.
+    #[serde(default)]
+    website: String,
+    /// The `FormToken` from `/subscribe/form`.
+    form_started: Option<String>,
+    /// Plain forms post the provider's own field.
+    #[serde(alias = "h-captcha-response", alias = "cf-turnstile-response")]
+    challenge_response: Option<String>,
+}
+
+impl BotCheckFields {
+    fn signals(&self) -> SignupSignals<'_> {
+        SignupSignals {
+            honeypot: &self.website,
+            form_token: self.form_started.as_deref(),
+            challenge_response: self.challenge_response.as_deref(),
+        }
+    }
+}
+
+/// Signup forms post urlencoded fields, the embeddable widget posts JSON.
+pub type SignupBody = Either<Json<FormData>, Form<FormData>>;
+
+fn form_data(body: &SignupBody) -> &FormData {
+    match body {
+        Either::Left(json) => json,
+        Either::Right(form) => form,
+    }
+}
+
+/// The fields that were rejected, with a message the signup widget shows next to each.
+#[derive(Serialize, Debug, Default)]
+pub struct SignupErrors {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<&'static str>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = SignupErrors;
 
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(form.name)?;
-        let email = SubscriberEmail::parse(form.email)?;
-        Ok(NewSubscriber { name, email })
+        match (
+            SubscriberName::parse(form.name),
+            SubscriberEmail::parse(form.email),
+        ) {
+            (Ok(name), Ok(email)) => Ok(NewSubscriber { name, email }),
+            (name, email) => Err(SignupErrors {
+                name: name.err().map(|_| "Please enter your name."),
+                email: email.err().map(|_| "Please enter a valid email address."),
+            }),
+        }
     }
 }
 
 #[tracing::instrument(
     name ="Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(body, request, repository, hmac_secret, bot_protection),
     fields(
-        subscriber_name=%form.name,
-        subscriber_email=%form.email
+        subscriber_name=%redact_name(&form_data(&body).name),
+        subscriber_email=%redact_email(&form_data(&body).email)
     )
 )]
 pub async fn subscribe(
-    form: Form<FormData>,
-    pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<ApplicationBaseUrl>,
+    body: SignupBody,
+    request: HttpRequest,
+    repository: web::Data<dyn SubscriberRepository>,
+    hmac_secret: web::Data<HmacSecret>,
+    bot_protection: web::Data<BotProtection>,
 ) -> HttpResponse {
     log::info!("Saving new subscriber details to the database");
-    let new_subscriber = match form.0.try_into() {
+    let is_json = matches!(body, Either::Left(_));
+    let mut form = body.into_inner();
+    let bot_check = std::mem::take(&mut form.bot_check);
+    let new_subscriber: NewSubscriber = match form.try_into() {
         Ok(sub) => sub,
+        Err(errors) if is_json => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "errors": errors }))
+        }
         Err(_) => return HttpResponse::BadRequest().finish(),
     };
 
-    let mut transaction = match pool.begin().await {
-        Ok(transaction) => transaction,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-
-    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber).await {
-        Ok(subscriber_id) => subscriber_id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-
-    let subscription_token = generate_subscription_token();
-    if store_token(&mut transaction, subscriber_id, &subscription_token)
+    // Bots are told they succeeded, so they have no reason to adapt.
+    match bot_protection
+        .check(&hmac_secret.0, &bot_check.signals(), Utc::now())
         .await
-        .is_err()
     {
-        return HttpResponse::InternalServerError().finish();
+        Ok(None) => {}
+        Ok(Some(suspicion)) => {
+            tracing::warn!("Ignoring a suspected bot signup: {}", suspicion);
+            return HttpResponse::Ok().finish();
+        }
+        Err(e) => {
+            tracing::error!("Unable to verify the challenge response: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
     }
 
-    if transaction.commit().await.is_err() {
-        return HttpResponse::InternalServerError().finish();
-    }
-
-    if send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .is_err()
-    {
+    // The confirmation link is only rendered when the email is sent.
+    let subscriber_id = Uuid::new_v4();
+    let pending = PendingSubscriber {
+        id: subscriber_id,
+        subscriber: &new_subscriber,
+        signup_origin: signup_origin(&request),
+        confirmation_email: OutboxEmail {
+            recipient: new_subscriber.email.as_ref(),
+            template: CONFIRMATION_TEMPLATE,
+            subject: CONFIRMATION_SUBJECT,
+            subscriber_id,
+        },
+    };
+    if let Err(e) = repository.add_pending_subscriber(pending).await {
+        tracing::error!("Failed to store the new subscriber: {}", e);
         return HttpResponse::InternalServerError().finish();
     }
 
     HttpResponse::Ok().finish()
 }
 
-fn generate_subscription_token() -> String {
-    let mut rng = rand::thread_rng();
-    Alphanumeric.sample_string(&mut rng, 25)
+/// The site the form was submitted from. Sandboxed pages and some privacy
+/// settings make browsers send `null` instead, which is not recorded.
+fn signup_origin(request: &HttpRequest) -> Option<String> {
+    let origin = request.headers().get(ORIGIN)?.to_str().ok()?;
+    let origin = Url::parse(origin).ok()?.origin();
+    origin.is_tuple().then(|| origin.ascii_serialization())
 }
 
-#[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
-)]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
-    base_url: &str,
-    subscription_token: &str,
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!(
-        "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
-
-    tracing::debug!("Confirmation link: {}", &confirmation_link);
-
-    email_client
-        .send_email(
-            new_subscriber.email,
-            "Welcome !",
-            &format!(
-                "Welcome to our newsletter!<br/> \
-                    Click <a href=\"{}\">here</a> to confirm the subscription.",
-                confirmation_link
-            ),
-            &format!(
-                "Welcome to our newsletter! \n Visit {} to confirm your subscription.",
-                confirmation_link
-            ),
-        )
-        .await
+#[derive(Serialize)]
+pub struct SignupForm {
+    form_started: String,
+    challenge: Option<ChallengeWidget>,
 }
 
-#[tracing::instrument(
-    name = "Saving new subscriber details in the database",
-    skip(new_subscriber, transaction)
-)]
-async fn insert_subscriber(
-    transaction: &mut Transaction<'_, Postgres>,
-    new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
-        r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
-        "#,
-        subscriber_id,
-        new_subscriber.email.as_ref(),
-        new_subscriber.name.as_ref(),
-        Utc::now(),
-    )
-    .execute(transaction)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-
-    Ok(subscriber_id)
+/// What the signup widget fetches before showing the form: a fresh form token
+/// and the challenge to render, if one is configured.
+pub async fn signup_form(
+    hmac_secret: web::Data<HmacSecret>,
+    bot_protection: web::Data<BotProtection>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(SignupForm {
+            form_started: FormToken::issue(&hmac_secret.0, Utc::now())
+                .as_ref()
+                .to_string(),
+            challenge: bot_protection.challenge_widget(),
+        })
 }
-#[tracing::instrument(
-    name = "Store subscription token in the database",
-    skip(subscription_token, transaction)
-)]
-pub async fn store_token(
-    transaction: &mut Transaction<'_, Postgres>,
-    subscriber_id: Uuid,
-    subscription_token: &str,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+
+/// Replaces any subscription tokens stored in plaintext, from before tokens were
+/// hashed at rest, with their keyed hash. Returns the number of tokens rehashed.
+#[tracing::instrument(name = "Rehash legacy subscription tokens", skip(pool, hmac_secret))]
+pub async fn rehash_legacy_subscription_tokens(
+    pool: &PgPool,
+    hmac_secret: &Secret<String>,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let legacy_tokens = sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)
-    "#,
-        subscription_token,
-        subscriber_id
+        SELECT subscription_token AS "subscription_token!"
+        FROM subscription_tokens
+        WHERE subscription_token IS NOT NULL
+        FOR UPDATE
+        "#
     )
-    .execute(transaction)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query {:?}", e);
-        e
-    })?;
-
-    Ok(())
+    .fetch_all(&mut transaction)
+    .await?;
+
+    for row in &legacy_tokens {
+        match SubscriptionToken::parse(row.subscription_token.clone()) {
+            Ok(token) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE subscription_tokens
+                    SET subscription_token_hash = $2, subscription_token = NULL
+                    WHERE subscription_token = $1
+                    "#,
+                    row.subscription_token,
+                    token.hash(hmac_secret)
+                )
+                .execute(&mut transaction)
+                .await?;
+            }
+            // Tokens without the expected format could never have been confirmed.
+            Err(_) => {
+                sqlx::query!(
+                    r#"DELETE FROM subscription_tokens WHERE subscription_token = $1"#,
+                    row.subscription_token
+                )
+                .execute(&mut transaction)
+                .await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(legacy_tokens.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+    use secrecy::Secret;
+
+    use super::*;
+    use crate::configuration::BotProtectionSettings;
+    use crate::domain::SubscriptionStatus;
+    use crate::subscriber_repository::InMemorySubscriberRepository;
+
+    fn hmac_secret() -> Secret<String> {
+        Secret::new("hmac-secret".to_string())
+    }
+
+    /// A token for a form loaded long enough ago to pass the minimum fill time.
+    fn form_started() -> String {
+        FormToken::issue(&hmac_secret(), Utc::now() - chrono::Duration::seconds(10))
+            .as_ref()
+            .to_string()
+    }
+
+    async fn send(
+        repository: Arc<InMemorySubscriberRepository>,
+        request: test::TestRequest,
+    ) -> ServiceResponse {
+        let repository: Arc<dyn SubscriberRepository> = repository;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(repository))
+                .app_data(web::Data::new(HmacSecret(hmac_secret())))
+                .app_data(web::Data::new(BotProtection::new(BotProtectionSettings {
+                    min_fill_seconds: 3,
+                    max_form_age_hours: 1,
+                    challenge: None,
+                })))
+                .route("/subscribe", web::post().to(subscribe)),
+        )
+        .await;
+        test::call_service(&app, request.to_request()).await
+    }
+
+    async fn post_subscription(
+        repository: Arc<InMemorySubscriberRepository>,
+        body: &'static str,
+    ) -> StatusCode {
+        let request = test::TestRequest::post()
+            .uri("/subscribe")
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .set_payload(format!("{}&form_started={}", body, form_started()));
+        send(repository, request).await.status()
+    }
+
+    fn json_post(mut body: serde_json::Value) -> test::TestRequest {
+        body["form_started"] = form_started().into();
+        test::TestRequest::post().uri("/subscribe").set_json(body)
+    }
+
+    #[actix_web::test]
+    async fn a_valid_form_stores_a_pending_subscriber_and_queues_their_confirmation() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+
+        let status = post_subscription(
+            repository.clone(),
+            "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let subscribers = repository.subscribers();
+        assert_eq!(subscribers.len(), 1);
+        assert_eq!(subscribers[0].1.email, "ursula_le_guin@gmail.com");
+        assert_eq!(
+            subscribers[0].1.status,
+            SubscriptionStatus::PendingConfirmation
+        );
+
+        let emails = repository.queued_emails();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].recipient, "ursula_le_guin@gmail.com");
+        assert_eq!(emails[0].template, CONFIRMATION_TEMPLATE);
+        assert_eq!(emails[0].subscriber_id, subscribers[0].0);
+    }
+
+    #[actix_web::test]
+    async fn an_invalid_form_stores_nothing() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+
+        let status =
+            post_subscription(repository.clone(), "name=&email=ursula_le_guin%40gmail.com").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(repository.subscribers().is_empty());
+        assert!(repository.queued_emails().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn a_failure_to_store_the_subscriber_returns_a_500() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+        let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+        post_subscription(repository.clone(), body).await;
+
+        let status = post_subscription(repository.clone(), body).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(repository.queued_emails().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn json_signups_record_their_origin() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+        let request = json_post(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .insert_header((ORIGIN, "https://www.example.com"));
+
+        let response = send(repository.clone(), request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let (_, subscriber) = repository.subscribers().remove(0);
+        assert_eq!(
+            subscriber.signup_origin.as_deref(),
+            Some("https://www.example.com")
+        );
+    }
+
+    #[actix_web::test]
+    async fn an_opaque_origin_is_not_recorded() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+        let request = json_post(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .insert_header((ORIGIN, "null"));
+
+        send(repository.clone(), request).await;
+
+        let (_, subscriber) = repository.subscribers().remove(0);
+        assert_eq!(subscriber.signup_origin, None);
+    }
+
+    #[actix_web::test]
+    async fn invalid_json_signups_are_told_which_fields_to_fix() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+        let request = json_post(serde_json::json!({
+            "name": "le guin",
+            "email": "not-an-email"
+        }));
+
+        let response = send(repository.clone(), request).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "errors": { "email": "Please enter a valid email address." } })
+        );
+        assert!(repository.subscribers().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn suspected_bots_are_told_they_succeeded_but_nothing_is_stored() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+
+        let status = post_subscription(
+            repository.clone(),
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=spam.example.com",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(repository.subscribers().is_empty());
+        assert!(repository.queued_emails().is_empty());
+    }
 }
@@ -1,60 +1,259 @@
 use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use secrecy::Secret;
 use serde::Deserialize;
-use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::configuration::ConfirmationLinkSettings;
+use crate::domain::{SignedConfirmation, SubscriptionStatus, SubscriptionToken};
+use crate::reload::Reloadable;
+use crate::startup::HmacSecret;
+use crate::subscriber_repository::{StatusChange, SubscriberRepository};
+
+/// A confirmation link either carries a stored `subscription_token`, or the
+/// claims of a `SignedConfirmation` together with the key ID and signature.
 #[derive(Deserialize)]
 pub struct Parameters {
-    subscription_token: String,
+    subscription_token: Option<String>,
+    subscriber_id: Option<Uuid>,
+    list: Option<String>,
+    expires: Option<i64>,
+    key_id: Option<String>,
+    signature: Option<String>,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters))]
-pub async fn confirm(pool: web::Data<PgPool>, parameters: web::Query<Parameters>) -> HttpResponse {
-    let subscription_id = match get_subscription_id(&parameters.subscription_token, &pool).await {
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(repository, parameters, hmac_secret, confirmation_links)
+)]
+pub async fn confirm(
+    repository: web::Data<dyn SubscriberRepository>,
+    parameters: web::Query<Parameters>,
+    hmac_secret: web::Data<HmacSecret>,
+    confirmation_links: web::Data<Reloadable<ConfirmationLinkSettings>>,
+) -> HttpResponse {
+    let parameters = parameters.into_inner();
+    if let (Some(subscriber_id), Some(list), Some(expires_at), Some(key_id), Some(signature)) = (
+        parameters.subscriber_id,
+        parameters.list,
+        parameters.expires,
+        parameters.key_id,
+        parameters.signature,
+    ) {
+        let confirmation = SignedConfirmation {
+            subscriber_id,
+            list,
+            expires_at,
+        };
+        return confirm_signed_link(
+            repository.get_ref(),
+            &confirmation_links.current(),
+            confirmation,
+            &key_id,
+            &signature,
+        )
+        .await;
+    }
+
+    let subscription_token = match parameters.subscription_token {
+        Some(subscription_token) => subscription_token,
+        None => return HttpResponse::BadRequest().finish(),
+    };
+    let subscription_token = match SubscriptionToken::parse(subscription_token) {
+        Ok(subscription_token) => subscription_token,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    let subscription_id = match get_subscription_id(
+        repository.get_ref(),
+        &subscription_token,
+        &hmac_secret.0,
+    )
+    .await
+    {
         Ok(subscription_id) => subscription_id,
-        Err(e) => return HttpResponse::InternalServerError().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
     };
 
     match subscription_id {
         None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+        Some(subscriber_id) => confirm_subscriber(repository.get_ref(), subscriber_id).await,
+    }
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber from a signed link",
+    skip(repository, settings, confirmation, signature),
+    fields(subscriber_id = %confirmation.subscriber_id)
+)]
+async fn confirm_signed_link(
+    repository: &dyn SubscriberRepository,
+    settings: &ConfirmationLinkSettings,
+    confirmation: SignedConfirmation,
+    key_id: &str,
+    signature: &str,
+) -> HttpResponse {
+    let key = match settings.key(key_id) {
+        Some(key) => key,
+        None => {
+            tracing::warn!("The confirmation link was signed with an unknown or retired key");
+            return HttpResponse::Unauthorized().finish();
         }
+    };
+    if !confirmation.has_valid_signature(&key.secret, signature) {
+        tracing::warn!("The confirmation link has an invalid signature");
+        return HttpResponse::Unauthorized().finish();
+    }
+    if confirmation.list != settings.list {
+        tracing::warn!("The confirmation link is for another list");
+        return HttpResponse::Unauthorized().finish();
+    }
+    if confirmation.is_expired(Utc::now()) {
+        tracing::warn!("The confirmation link has expired");
+        return HttpResponse::Unauthorized().finish();
     }
+
+    confirm_subscriber(repository, confirmation.subscriber_id).await
 }
 
 async fn get_subscription_id(
-    subscription_token: &str,
-    pool: &PgPool,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"select subscriber_id from subscription_tokens where subscription_token= $1"#,
-        subscription_token
-    )
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {}", e);
-        e
-    })?;
+    repository: &dyn SubscriberRepository,
+    subscription_token: &SubscriptionToken,
+    hmac_secret: &Secret<String>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    repository
+        .find_subscriber_by_token(&subscription_token.hash(hmac_secret))
+        .await
+}
 
-    Ok(result.map(|r| r.subscriber_id))
+async fn confirm_subscriber(
+    repository: &dyn SubscriberRepository,
+    subscriber_id: Uuid,
+) -> HttpResponse {
+    match repository
+        .change_status(subscriber_id, SubscriptionStatus::Confirmed)
+        .await
+    {
+        Ok(StatusChange::Applied) => HttpResponse::Ok().finish(),
+        Ok(StatusChange::UnknownSubscriber) => HttpResponse::Unauthorized().finish(),
+        Ok(StatusChange::Rejected(e)) => {
+            tracing::warn!("Refusing to confirm the subscriber: {}", e);
+            HttpResponse::Conflict().finish()
+        }
+        Err(e) => {
+            tracing::error!("Failed to confirm the subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
 }
 
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"update subscriptions set status = 'confirmed' where id = $1"#,
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    use super::*;
+    use crate::configuration::ConfirmationLinkMode;
+    use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+    use crate::outbox_dispatcher::OutboxEmail;
+    use crate::subscriber_repository::{InMemorySubscriberRepository, PendingSubscriber};
+
+    fn hmac_secret() -> Secret<String> {
+        Secret::new("hmac-secret".to_string())
+    }
+
+    /// Stores a pending subscriber whose confirmation link carries `token`.
+    async fn store_pending_subscriber(
+        repository: &InMemorySubscriberRepository,
+        token: &SubscriptionToken,
+    ) -> Uuid {
+        let subscriber_id = Uuid::new_v4();
+        let new_subscriber = NewSubscriber {
+            email: SubscriberEmail::parse("ursula_le_guin@gmail.com".to_string()).unwrap(),
+            name: SubscriberName::parse("le guin".to_string()).unwrap(),
+        };
+        let pending = PendingSubscriber {
+            id: subscriber_id,
+            subscriber: &new_subscriber,
+            signup_origin: None,
+            confirmation_email: OutboxEmail {
+                recipient: "ursula_le_guin@gmail.com",
+                template: "subscription_confirmation",
+                subject: "Welcome !",
+                subscriber_id,
+            },
+        };
+        repository.add_pending_subscriber(pending).await.unwrap();
+        repository.store_token(token.hash(&hmac_secret()), subscriber_id);
         subscriber_id
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    }
+
+    async fn confirm_with(
+        repository: Arc<InMemorySubscriberRepository>,
+        token: &SubscriptionToken,
+    ) -> StatusCode {
+        let repository: Arc<dyn SubscriberRepository> = repository;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(repository))
+                .app_data(web::Data::new(HmacSecret(hmac_secret())))
+                .app_data(web::Data::new(Reloadable::new(ConfirmationLinkSettings {
+                    mode: ConfirmationLinkMode::Token,
+                    list: "newsletter".to_string(),
+                    validity_hours: 1,
+                    signing_keys: vec![],
+                })))
+                .route("/subscriptions/confirm", web::get().to(confirm)),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri(&format!(
+                "/subscriptions/confirm?subscription_token={}",
+                token.as_ref()
+            ))
+            .to_request();
+        test::call_service(&app, request).await.status()
+    }
+
+    #[actix_web::test]
+    async fn a_known_token_confirms_the_subscriber() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+        let token = SubscriptionToken::generate();
+        let subscriber_id = store_pending_subscriber(&repository, &token).await;
 
-    Ok(())
+        let status = confirm_with(repository.clone(), &token).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let subscriber = repository.subscriber(subscriber_id).unwrap();
+        assert_eq!(subscriber.status, SubscriptionStatus::Confirmed);
+    }
+
+    #[actix_web::test]
+    async fn an_unknown_token_is_rejected_with_401() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+        store_pending_subscriber(&repository, &SubscriptionToken::generate()).await;
+
+        let status = confirm_with(repository, &SubscriptionToken::generate()).await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn an_unsubscribed_subscriber_is_not_confirmed_again() {
+        let repository = Arc::new(InMemorySubscriberRepository::default());
+        let token = SubscriptionToken::generate();
+        let subscriber_id = store_pending_subscriber(&repository, &token).await;
+        repository
+            .change_status(subscriber_id, SubscriptionStatus::Unsubscribed)
+            .await
+            .unwrap();
+
+        let status = confirm_with(repository.clone(), &token).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        let subscriber = repository.subscriber(subscriber_id).unwrap();
+        assert_eq!(subscriber.status, SubscriptionStatus::Unsubscribed);
+    }
 }
@@ -0,0 +1,31 @@
+use actix_web::http::header::{
+    CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+    X_FRAME_OPTIONS,
+};
+use actix_web::middleware::DefaultHeaders;
+
+use crate::configuration::SecuritySettings;
+
+/// Added to every response that doesn't set them itself. Confirmation links
+/// carry tokens in their query string, so no referrer is ever sent.
+pub fn security_headers(settings: &SecuritySettings) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add((
+            CONTENT_SECURITY_POLICY,
+            settings.content_security_policy.clone(),
+        ))
+        .add((X_FRAME_OPTIONS, "DENY"))
+        .add((REFERRER_POLICY, "no-referrer"))
+        .add((X_CONTENT_TYPE_OPTIONS, "nosniff"));
+    if settings.hsts_max_age_seconds > 0 {
+        headers.add((
+            STRICT_TRANSPORT_SECURITY,
+            format!(
+                "max-age={}; includeSubDomains",
+                settings.hsts_max_age_seconds
+            ),
+        ))
+    } else {
+        headers
+    }
+}
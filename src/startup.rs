@@ -1,69 +1,231 @@
-use std::time::Duration;
-use std::{io::Error, net::TcpListener};
+use std::{io::Error, net::TcpListener, sync::Arc};
 
+use actix_web::middleware::from_fn;
 use actix_web::{dev::Server, HttpServer};
 use actix_web::{web, App};
-use sqlx::postgres::PgPoolOptions;
+use secrecy::Secret;
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
-use crate::configuration::Settings;
-use crate::email_client::EmailClient;
+use crate::bot_protection::BotProtection;
+use crate::configuration::{AppSettings, SecuritySettings, Settings};
+use crate::cors::signup_cors;
+use crate::csrf::csrf_protection;
+use crate::migrations::{check_schema, run_migrations};
+use crate::reload::SettingsReloader;
+use crate::routes::admin::{
+    create_api_key, create_user, delete_api_key, get_api_keys, get_audit_events, get_deliveries,
+    get_log_filter, get_subscribers, update_log_filter, update_user_role,
+};
 use crate::routes::health_check;
-use crate::routes::subscriptions::subscribe;
+use crate::routes::https_redirect::redirect_to_https;
+use crate::routes::newsletters::publish_newsletter;
+use crate::routes::signup_widget::signup_widget;
+use crate::routes::subscriptions::{rehash_legacy_subscription_tokens, signup_form, subscribe};
 use crate::routes::subscriptions_confirm::confirm;
+use crate::security_headers::security_headers;
+use crate::subscriber_repository::{PostgresSubscriberRepository, SubscriberRepository};
+use crate::telemetry::{LogFilter, RedactedRootSpan};
+use crate::tls::{load_certified_key, server_config, CertificateResolver};
 
 pub struct Application {
     port: u16,
     server: Server,
+    http_redirect: Option<(u16, Server)>,
+    settings_reloader: SettingsReloader,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Application, std::io::Error> {
+    pub async fn build(
+        configuration: Settings,
+        log_filter: LogFilter,
+    ) -> Result<Application, std::io::Error> {
+        let migrate_on_startup = configuration.database.migrate_on_startup;
+        let certificates = match &configuration.application.tls {
+            Some(tls) => {
+                let certified_key = load_certified_key(tls).map_err(std::io::Error::other)?;
+                Some(Arc::new(CertificateResolver::new(certified_key)))
+            }
+            None => None,
+        };
+        let settings_reloader = SettingsReloader::new(&configuration, log_filter, certificates)
+            .map_err(std::io::Error::other)?;
         let pg_pool = configuration.database.get_connection_pool();
-        let email_client = configuration.email_client.email_client();
+
+        if migrate_on_startup {
+            run_migrations(&pg_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        } else {
+            check_schema(&pg_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+
+        let rehashed =
+            rehash_legacy_subscription_tokens(&pg_pool, &configuration.application.hmac_secret)
+                .await
+                .map_err(std::io::Error::other)?;
+        if rehashed > 0 {
+            tracing::info!("Rehashed {} legacy subscription tokens", rehashed);
+        }
 
         let listener = TcpListener::bind(format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         ))
-        .expect(format!("Unable to bind to port {}", configuration.application.port).as_str());
+        .unwrap_or_else(|_| panic!("Unable to bind to port {}", configuration.application.port));
         let port = listener.local_addr().unwrap().port();
         let base_url = format!("{}:{}", configuration.application.base_url, port);
-        let server = run(listener, pg_pool, email_client, base_url).await?;
-        Ok(Application { port, server })
+
+        let http_redirect_port = configuration
+            .application
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.http_redirect_port);
+        let http_redirect = match http_redirect_port {
+            Some(http_redirect_port) => {
+                let listener = TcpListener::bind(format!(
+                    "{}:{}",
+                    configuration.application.host, http_redirect_port
+                ))
+                .unwrap_or_else(|_| panic!("Unable to bind to port {}", http_redirect_port));
+                let http_redirect_port = listener.local_addr().unwrap().port();
+                Some((
+                    http_redirect_port,
+                    run_http_redirect(listener, base_url.clone())?,
+                ))
+            }
+            None => None,
+        };
+
+        let server = run(
+            listener,
+            pg_pool,
+            &settings_reloader,
+            base_url,
+            configuration.application,
+            configuration.security,
+            BotProtection::new(configuration.bot_protection),
+        )
+        .await?;
+        Ok(Application {
+            port,
+            server,
+            http_redirect,
+            settings_reloader,
+        })
     }
 
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        match self.http_redirect {
+            Some((_, http_redirect)) => {
+                tokio::try_join!(self.server, http_redirect)?;
+                Ok(())
+            }
+            None => self.server.await,
+        }
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    pub fn http_redirect_port(&self) -> Option<u16> {
+        self.http_redirect.as_ref().map(|(port, _)| *port)
+    }
+
+    pub fn settings_reloader(&self) -> SettingsReloader {
+        self.settings_reloader.clone()
+    }
 }
 
 pub async fn run(
     listener: TcpListener,
     _pool: PgPool,
-    _email_client: EmailClient,
+    settings_reloader: &SettingsReloader,
     _base_url: String,
+    application: AppSettings,
+    security: SecuritySettings,
+    bot_protection: BotProtection,
 ) -> Result<Server, Error> {
+    let subscriber_repository: Arc<dyn SubscriberRepository> =
+        Arc::new(PostgresSubscriberRepository::new(_pool.clone()));
+    let subscriber_repository = web::Data::from(subscriber_repository);
     let pool = web::Data::new(_pool);
-    let email_client = web::Data::new(_email_client);
+    let email_client = web::Data::new(settings_reloader.email_client());
     let base_url = web::Data::new(ApplicationBaseUrl(_base_url));
+    let hmac_secret = web::Data::new(HmacSecret(application.hmac_secret));
+    let cors_allowed_origins = application.cors_allowed_origins;
+    let bot_protection = web::Data::new(bot_protection);
+    let confirmation_links = web::Data::new(settings_reloader.confirmation_links());
+    let log_filter = web::Data::new(settings_reloader.log_filter());
+    let cookies = web::Data::new(security.cookies.clone());
+    let tls = settings_reloader.certificates().map(server_config);
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(from_fn(csrf_protection))
+            .wrap(security_headers(&security))
+            .wrap(TracingLogger::<RedactedRootSpan>::new())
             .app_data(pool.clone())
+            .app_data(subscriber_repository.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(confirmation_links.clone())
+            .app_data(log_filter.clone())
+            .app_data(cookies.clone())
+            .app_data(bot_protection.clone())
             .route("/health_check", web::get().to(health_check))
-            .route("/subscribe", web::post().to(subscribe))
+            .service(
+                web::resource("/subscribe")
+                    .wrap(signup_cors(&cors_allowed_origins))
+                    .route(web::post().to(subscribe)),
+            )
+            .service(
+                web::resource("/subscribe/form")
+                    .wrap(signup_cors(&cors_allowed_origins))
+                    .route(web::get().to(signup_form)),
+            )
+            .route("/embed/signup.js", web::get().to(signup_widget))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/admin/deliveries", web::get().to(get_deliveries))
+            .route("/admin/api_keys", web::post().to(create_api_key))
+            .route("/admin/api_keys", web::get().to(get_api_keys))
+            .route("/admin/api_keys/{key_id}", web::delete().to(delete_api_key))
+            .route("/admin/users", web::post().to(create_user))
+            .route(
+                "/admin/users/{user_id}/role",
+                web::put().to(update_user_role),
+            )
+            .route("/admin/audit", web::get().to(get_audit_events))
+            .route("/admin/subscribers", web::get().to(get_subscribers))
+            .route("/admin/log_filter", web::get().to(get_log_filter))
+            .route("/admin/log_filter", web::put().to(update_log_filter))
+    });
+    let server = match tls {
+        Some(tls) => server.listen_rustls_0_23(listener, tls)?,
+        None => server.listen(listener)?,
+    }
+    .run();
+
+    Ok(server)
+}
+
+fn run_http_redirect(listener: TcpListener, base_url: String) -> Result<Server, Error> {
+    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::<RedactedRootSpan>::new())
+            .app_data(base_url.clone())
+            .default_service(web::to(redirect_to_https))
     })
     .listen(listener)?
     .run();
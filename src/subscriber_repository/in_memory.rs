@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use super::{AlreadySubscribed, PendingSubscriber, StatusChange, SubscriberRepository};
+use crate::domain::SubscriptionStatus;
+
+/// Keeps subscribers in memory, for exercising route logic without a database.
+#[derive(Default)]
+pub struct InMemorySubscriberRepository {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    subscribers: HashMap<Uuid, StoredSubscriber>,
+    tokens: HashMap<String, Uuid>,
+    outbox: Vec<QueuedEmail>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredSubscriber {
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub signup_origin: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueuedEmail {
+    pub recipient: String,
+    pub template: String,
+    pub subject: String,
+    pub subscriber_id: Uuid,
+}
+
+impl InMemorySubscriberRepository {
+    pub fn subscriber(&self, subscriber_id: Uuid) -> Option<StoredSubscriber> {
+        self.state().subscribers.get(&subscriber_id).cloned()
+    }
+
+    pub fn subscribers(&self) -> Vec<(Uuid, StoredSubscriber)> {
+        self.state()
+            .subscribers
+            .iter()
+            .map(|(id, subscriber)| (*id, subscriber.clone()))
+            .collect()
+    }
+
+    pub fn queued_emails(&self) -> Vec<QueuedEmail> {
+        self.state().outbox.clone()
+    }
+
+    /// Stands in for the outbox dispatcher, which stores the hash of the token
+    /// in a confirmation email once it has been sent.
+    pub fn store_token(&self, token_hash: String, subscriber_id: Uuid) {
+        self.state().tokens.insert(token_hash, subscriber_id);
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("The repository lock is poisoned")
+    }
+}
+
+#[async_trait]
+impl SubscriberRepository for InMemorySubscriberRepository {
+    async fn add_pending_subscriber(
+        &self,
+        pending: PendingSubscriber<'_>,
+    ) -> Result<(), anyhow::Error> {
+        let mut state = self.state();
+        let email = pending.subscriber.email.as_ref();
+        // Mirrors the unique constraint on `subscriptions.email`.
+        if state.subscribers.values().any(|s| s.email == email) {
+            return Err(AlreadySubscribed.into());
+        }
+
+        state.subscribers.insert(
+            pending.id,
+            StoredSubscriber {
+                email: email.to_string(),
+                name: pending.subscriber.name.as_ref().to_string(),
+                status: SubscriptionStatus::PendingConfirmation,
+                signup_origin: pending.signup_origin,
+            },
+        );
+        let email = pending.confirmation_email;
+        state.outbox.push(QueuedEmail {
+            recipient: email.recipient.to_string(),
+            template: email.template.to_string(),
+            subject: email.subject.to_string(),
+            subscriber_id: email.subscriber_id,
+        });
+        Ok(())
+    }
+
+    async fn find_subscriber_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Uuid>, anyhow::Error> {
+        Ok(self.state().tokens.get(token_hash).copied())
+    }
+
+    async fn change_status(
+        &self,
+        subscriber_id: Uuid,
+        next: SubscriptionStatus,
+    ) -> Result<StatusChange, anyhow::Error> {
+        let mut state = self.state();
+        let subscriber = match state.subscribers.get_mut(&subscriber_id) {
+            Some(subscriber) => subscriber,
+            None => return Ok(StatusChange::UnknownSubscriber),
+        };
+        match subscriber.status.transition_to(next) {
+            Ok(next) => {
+                subscriber.status = next;
+                Ok(StatusChange::Applied)
+            }
+            Err(e) => Ok(StatusChange::Rejected(e)),
+        }
+    }
+}
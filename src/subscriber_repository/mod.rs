@@ -0,0 +1,66 @@
+//! Persistence for subscribers, so route logic does not depend on Postgres.
+
+mod in_memory;
+mod postgres;
+
+use std::fmt;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::outbox_dispatcher::OutboxEmail;
+
+pub use in_memory::{InMemorySubscriberRepository, QueuedEmail, StoredSubscriber};
+pub use postgres::PostgresSubscriberRepository;
+
+/// Everything written when someone signs up. It is stored all or nothing.
+pub struct PendingSubscriber<'a> {
+    pub id: Uuid,
+    pub subscriber: &'a NewSubscriber,
+    /// The site the signup was submitted from, when the browser said.
+    pub signup_origin: Option<String>,
+    pub confirmation_email: OutboxEmail<'a>,
+}
+
+/// The email address has already signed up. The address is left out, so the
+/// error can be logged as is.
+#[derive(Debug)]
+pub struct AlreadySubscribed;
+
+impl fmt::Display for AlreadySubscribed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The email address is already subscribed")
+    }
+}
+
+impl std::error::Error for AlreadySubscribed {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StatusChange {
+    Applied,
+    UnknownSubscriber,
+    /// `SubscriptionStatus::transition_to` does not allow the change.
+    Rejected(String),
+}
+
+#[async_trait]
+pub trait SubscriberRepository: Send + Sync {
+    async fn add_pending_subscriber(
+        &self,
+        pending: PendingSubscriber<'_>,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Looked up by the keyed hash, which an attacker cannot compute without the
+    /// secret, so timing the lookup tells them nothing about stored tokens.
+    async fn find_subscriber_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Uuid>, anyhow::Error>;
+
+    async fn change_status(
+        &self,
+        subscriber_id: Uuid,
+        next: SubscriptionStatus,
+    ) -> Result<StatusChange, anyhow::Error>;
+}
@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{AlreadySubscribed, PendingSubscriber, StatusChange, SubscriberRepository};
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::outbox_dispatcher::enqueue_email;
+
+pub struct PostgresSubscriberRepository {
+    pool: PgPool,
+}
+
+impl PostgresSubscriberRepository {
+    pub fn new(pool: PgPool) -> PostgresSubscriberRepository {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SubscriberRepository for PostgresSubscriberRepository {
+    #[tracing::instrument(
+        name = "Saving new subscriber details in the database",
+        skip(self, pending),
+        fields(subscriber_id = %pending.id)
+    )]
+    async fn add_pending_subscriber(
+        &self,
+        pending: PendingSubscriber<'_>,
+    ) -> Result<(), anyhow::Error> {
+        let mut transaction = self.pool.begin().await?;
+        insert_subscriber(
+            &mut transaction,
+            pending.id,
+            pending.subscriber,
+            pending.signup_origin.as_deref(),
+        )
+        .await?;
+        enqueue_email(&mut transaction, pending.confirmation_email).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn find_subscriber_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<Uuid>, anyhow::Error> {
+        let result = sqlx::query!(
+            r#"
+            select subscriber_id
+            from subscription_tokens
+            where subscription_token_hash = $1
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {}", e);
+            e
+        })?;
+
+        Ok(result.map(|r| r.subscriber_id))
+    }
+
+    #[tracing::instrument(name = "Change the status of a subscriber", skip(self))]
+    async fn change_status(
+        &self,
+        subscriber_id: Uuid,
+        next: SubscriptionStatus,
+    ) -> Result<StatusChange, anyhow::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let current = sqlx::query!(
+            r#"select status as "status: SubscriptionStatus" from subscriptions where id = $1 for update"#,
+            subscriber_id
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        let current = match current {
+            Some(row) => row.status,
+            None => return Ok(StatusChange::UnknownSubscriber),
+        };
+        let next = match current.transition_to(next) {
+            Ok(next) => next,
+            Err(e) => return Ok(StatusChange::Rejected(e)),
+        };
+
+        sqlx::query!(
+            r#"update subscriptions set status = $2 where id = $1"#,
+            subscriber_id,
+            next as SubscriptionStatus
+        )
+        .execute(&mut transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        transaction.commit().await?;
+
+        Ok(StatusChange::Applied)
+    }
+}
+
+async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+    signup_origin: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, signup_origin)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        subscriber_id,
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+        signup_origin,
+    )
+    .execute(transaction)
+    .await
+    .map_err(|e| -> anyhow::Error {
+        if is_unique_violation(&e) {
+            return AlreadySubscribed.into();
+        }
+        // `Display` leaves out the offending values, which `Debug` includes.
+        tracing::error!("Failed to execute query: {}", e);
+        e.into()
+    })?;
+
+    Ok(())
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "23505")
+}
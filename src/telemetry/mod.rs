@@ -0,0 +1,131 @@
+mod rolling_file;
+mod root_span;
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::{subscriber::set_global_default, Subscriber};
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_subscriber::{
+    fmt::{self, writer::BoxMakeWriter, MakeWriter},
+    layer::Layered,
+    prelude::__tracing_subscriber_SubscriberExt,
+    reload, EnvFilter, Layer, Registry,
+};
+
+use crate::configuration::{LogFormat, LogSettings, LogSink, OtlpSettings};
+
+pub use rolling_file::RollingFile;
+pub use root_span::RedactedRootSpan;
+
+type FilteredRegistry = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// Changes which spans and events are recorded while the application is running.
+#[derive(Clone)]
+pub struct LogFilter(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilter {
+    pub fn current(&self) -> Result<String, reload::Error> {
+        self.0.with_current(|filter| filter.to_string())
+    }
+
+    pub fn replace(&self, filter: EnvFilter) -> Result<(), reload::Error> {
+        self.0.reload(filter)
+    }
+}
+
+pub fn get_log_sink(settings: &LogSettings) -> Result<BoxMakeWriter, std::io::Error> {
+    match settings.sink {
+        LogSink::Stdout => Ok(BoxMakeWriter::new(std::io::stdout)),
+        LogSink::File => Ok(BoxMakeWriter::new(RollingFile::new(&settings.file)?)),
+    }
+}
+
+/// Batches spans up and exports them to the collector at `settings.endpoint`.
+pub fn get_tracer_provider(
+    name: String,
+    settings: &OtlpSettings,
+) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&settings.endpoint)
+        .build()?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(name).build())
+        .build())
+}
+
+/// The returned `LogFilter` swaps `filter` out for another one later on.
+pub fn get_subscriber<Sink>(
+    name: String,
+    filter: String,
+    format: LogFormat,
+    sink: Sink,
+    tracer_provider: Option<&SdkTracerProvider>,
+) -> (impl Subscriber + Send + Sync, LogFilter)
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(filter));
+    let (filter_layer, filter_handle) = reload::Layer::new(env_filter);
+    let opentelemetry_layer = tracer_provider
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(name.clone())));
+    let formatting_layer: Box<dyn Layer<FilteredRegistry> + Send + Sync> = match format {
+        LogFormat::Bunyan => {
+            Box::new(JsonStorageLayer.and_then(BunyanFormattingLayer::new(name, sink)))
+        }
+        LogFormat::Pretty => Box::new(fmt::layer().pretty().with_writer(sink)),
+        LogFormat::Compact => Box::new(fmt::layer().compact().with_writer(sink)),
+    };
+    let subscriber = Registry::default()
+        .with(filter_layer)
+        .with(formatting_layer)
+        .with(opentelemetry_layer);
+    (subscriber, LogFilter(filter_handle))
+}
+
+/// Also installs the W3C trace context propagator, so incoming `traceparent`
+/// headers are honoured and passed on to the services we call.
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+    LogTracer::init().expect("Unable to set logger");
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    set_global_default(subscriber).expect("failed to set subscriber");
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn spans_are_exported_to_the_configured_collector() {
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&collector)
+            .await;
+
+        let settings = OtlpSettings {
+            endpoint: format!("{}/v1/traces", collector.uri()),
+        };
+        let provider = get_tracer_provider("test".into(), &settings).unwrap();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            LogFormat::Bunyan,
+            std::io::sink,
+            Some(&provider),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Exported span").in_scope(|| {});
+        });
+
+        provider.force_flush().unwrap();
+    }
+}
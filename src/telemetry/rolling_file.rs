@@ -0,0 +1,114 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use chrono::{DateTime, Utc};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::configuration::{LogFileSettings, LogRotation};
+
+/// Appends log lines to a file in `directory`, moving on to a new file
+/// whenever the rotation period changes.
+pub struct RollingFile {
+    directory: PathBuf,
+    prefix: String,
+    rotation: LogRotation,
+    current: Mutex<(String, File)>,
+}
+
+impl RollingFile {
+    pub fn new(settings: &LogFileSettings) -> io::Result<RollingFile> {
+        let directory = PathBuf::from(&settings.directory);
+        fs::create_dir_all(&directory)?;
+        let file_name = file_name(&settings.prefix, settings.rotation, Utc::now());
+        let file = open(&directory.join(&file_name))?;
+        Ok(RollingFile {
+            directory,
+            prefix: settings.prefix.clone(),
+            rotation: settings.rotation,
+            current: Mutex::new((file_name, file)),
+        })
+    }
+}
+
+impl Write for &RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        let file_name = file_name(&self.prefix, self.rotation, Utc::now());
+        if current.0 != file_name {
+            *current = (file_name.clone(), open(&self.directory.join(file_name))?);
+        }
+        current.1.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        current.1.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RollingFile {
+    type Writer = &'a RollingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+fn file_name(prefix: &str, rotation: LogRotation, now: DateTime<Utc>) -> String {
+    match rotation {
+        LogRotation::Hourly => format!("{}.{}", prefix, now.format("%Y-%m-%d-%H")),
+        LogRotation::Daily => format!("{}.{}", prefix, now.format("%Y-%m-%d")),
+        LogRotation::Never => prefix.to_string(),
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn file_names_change_with_the_rotation_period() {
+        let morning = Utc.with_ymd_and_hms(2023, 2, 20, 9, 15, 0).unwrap();
+        let noon = Utc.with_ymd_and_hms(2023, 2, 20, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            file_name("app", LogRotation::Hourly, morning),
+            "app.2023-02-20-09"
+        );
+        assert_ne!(
+            file_name("app", LogRotation::Hourly, morning),
+            file_name("app", LogRotation::Hourly, noon)
+        );
+        assert_eq!(
+            file_name("app", LogRotation::Daily, morning),
+            file_name("app", LogRotation::Daily, noon)
+        );
+        assert_eq!(file_name("app", LogRotation::Never, noon), "app");
+    }
+
+    #[test]
+    fn lines_are_appended_to_the_current_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let settings = LogFileSettings {
+            directory: directory.to_string_lossy().into_owned(),
+            prefix: "test".to_string(),
+            rotation: LogRotation::Never,
+        };
+        let rolling_file = RollingFile::new(&settings).unwrap();
+
+        rolling_file.make_writer().write_all(b"first\n").unwrap();
+        rolling_file.make_writer().write_all(b"second\n").unwrap();
+
+        let contents = fs::read_to_string(directory.join("test")).unwrap();
+        assert_eq!(contents, "first\nsecond\n");
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
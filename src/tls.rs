@@ -0,0 +1,135 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+use crate::configuration::TlsSettings;
+use crate::reload::Reloadable;
+
+/// Hands every new connection the certificate loaded last, so a renewed
+/// certificate is picked up without dropping open connections.
+pub struct CertificateResolver(Reloadable<CertifiedKey>);
+
+impl CertificateResolver {
+    pub fn new(key: CertifiedKey) -> CertificateResolver {
+        CertificateResolver(Reloadable::new(key))
+    }
+
+    pub fn replace(&self, key: CertifiedKey) {
+        self.0.replace(key)
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.current())
+    }
+}
+
+impl fmt::Debug for CertificateResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertificateResolver")
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads the certificate chain and private key, checking that they belong together.
+pub fn load_certified_key(settings: &TlsSettings) -> Result<CertifiedKey, anyhow::Error> {
+    let chain = CertificateDer::pem_file_iter(&settings.certificate_path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Unable to read {}", settings.certificate_path))?;
+    if chain.is_empty() {
+        anyhow::bail!("There is no certificate in {}", settings.certificate_path);
+    }
+    let private_key = PrivateKeyDer::from_pem_file(&settings.private_key_path)
+        .with_context(|| format!("Unable to read {}", settings.private_key_path))?;
+
+    let signing_key = provider()
+        .key_provider
+        .load_private_key(private_key)
+        .context("Unsupported private key")?;
+    let key = CertifiedKey::new(chain, signing_key);
+    key.keys_match()
+        .context("The private key does not match the certificate")?;
+    Ok(key)
+}
+
+pub fn server_config(resolver: Arc<CertificateResolver>) -> ServerConfig {
+    ServerConfig::builder_with_provider(Arc::new(provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
+
+fn provider() -> CryptoProvider {
+    ring::default_provider()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rcgen::{generate_simple_self_signed, CertifiedKey as GeneratedKey};
+
+    use super::*;
+
+    fn write_pem_files(
+        directory: &Path,
+        certificate: &GeneratedKey,
+        key: &GeneratedKey,
+    ) -> TlsSettings {
+        std::fs::create_dir_all(directory).unwrap();
+        let certificate_path = directory.join("cert.pem");
+        let private_key_path = directory.join("key.pem");
+        std::fs::write(&certificate_path, certificate.cert.pem()).unwrap();
+        std::fs::write(&private_key_path, key.key_pair.serialize_pem()).unwrap();
+        TlsSettings {
+            certificate_path: certificate_path.to_string_lossy().into_owned(),
+            private_key_path: private_key_path.to_string_lossy().into_owned(),
+            http_redirect_port: None,
+        }
+    }
+
+    #[test]
+    fn a_matching_certificate_and_key_are_loaded() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let generated = generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let settings = write_pem_files(&directory, &generated, &generated);
+
+        let key = load_certified_key(&settings).unwrap();
+
+        assert_eq!(key.cert.len(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_key_for_another_certificate_is_rejected() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let generated = generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let other = generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let settings = write_pem_files(&directory, &generated, &other);
+
+        assert!(load_certified_key(&settings).is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let settings = TlsSettings {
+            certificate_path: "/nonexistent/cert.pem".to_string(),
+            private_key_path: "/nonexistent/key.pem".to_string(),
+            http_redirect_port: None,
+        };
+
+        let error = load_certified_key(&settings).unwrap_err();
+
+        assert!(error.to_string().contains("/nonexistent/cert.pem"));
+    }
+}
@@ -0,0 +1,203 @@
+use wiremock::matchers::{method, path};
+use wiremock::Mock;
+
+use crate::helpers::{spawn_app, BatchResponder, TestApp};
+
+async fn audit_events(app: &TestApp, query: &[(&str, &str)]) -> serde_json::Value {
+    let response = app.get_audit_events(query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn creating_an_api_key_is_audited_with_its_request_context() {
+    let app = spawn_app().await;
+
+    let issued: serde_json::Value = app
+        .post_api_key(
+            &app.test_user.api_key,
+            serde_json::json!({ "name": "ci", "scopes": ["newsletters:publish"] }),
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let page = audit_events(&app, &[("action", "api_key.created")]).await;
+    let events = page["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(event["actor_id"], app.test_user.user_id.to_string());
+    assert_eq!(event["target"], issued["id"]);
+    assert_eq!(event["ip"], "127.0.0.1");
+    assert!(event["request_id"].is_string());
+    assert_eq!(event["diff"]["name"], "ci");
+    assert_eq!(
+        event["diff"]["scopes"],
+        serde_json::json!(["newsletters:publish"])
+    );
+    // The plaintext key never makes it into the audit log.
+    assert!(!event.to_string().contains(issued["key"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn forwarding_headers_sent_by_the_client_are_not_trusted_for_the_ip() {
+    let app = spawn_app().await;
+
+    reqwest::Client::new()
+        .post(format!("{}/admin/api_keys", &app.addr))
+        .bearer_auth(&app.test_user.api_key)
+        .header("X-Forwarded-For", "203.0.113.7")
+        .header("Forwarded", "for=203.0.113.7")
+        .json(&serde_json::json!({ "name": "ci", "scopes": ["newsletters:publish"] }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let page = audit_events(&app, &[("action", "api_key.created")]).await;
+    assert_eq!(page["events"][0]["ip"], "127.0.0.1");
+}
+
+#[tokio::test]
+async fn a_role_change_is_audited_with_the_previous_role() {
+    let app = spawn_app().await;
+    let viewer = app.store_user_with_role("viewer").await;
+
+    reqwest::Client::new()
+        .put(format!("{}/admin/users/{}/role", &app.addr, viewer.user_id))
+        .bearer_auth(&app.test_user.api_key)
+        .json(&serde_json::json!({ "role": "editor" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let page = audit_events(&app, &[("target", &viewer.user_id.to_string())]).await;
+    let event = &page["events"][0];
+    assert_eq!(event["action"], "user.role_changed");
+    assert_eq!(
+        event["diff"]["role"],
+        serde_json::json!({ "from": "viewer", "to": "editor" })
+    );
+}
+
+#[tokio::test]
+async fn publishing_a_newsletter_is_audited() {
+    let app = spawn_app().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Issue #1",
+        "content": { "text": "Plain text", "html": "<p>HTML</p>" }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let page = audit_events(&app, &[("action", "newsletter.published")]).await;
+    let event = &page["events"][0];
+    assert_eq!(event["target"], "Issue #1");
+    assert_eq!(event["diff"]["recipients"], 0);
+}
+
+#[tokio::test]
+async fn denied_requests_are_audited() {
+    let app = spawn_app().await;
+    let viewer = app.store_user_with_role("viewer").await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.addr))
+        .bearer_auth(&viewer.api_key)
+        .json(&serde_json::json!({
+            "title": "Issue #1",
+            "content": { "text": "Plain text", "html": "<p>HTML</p>" }
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let page = audit_events(&app, &[("actor_id", &viewer.user_id.to_string())]).await;
+    let event = &page["events"][0];
+    assert_eq!(event["action"], "access.denied");
+    assert_eq!(event["target"], "/newsletters");
+    assert_eq!(event["diff"]["permission"], "publish_newsletters");
+    assert_eq!(event["diff"]["role"], "viewer");
+}
+
+#[tokio::test]
+async fn audit_events_are_paginated_newest_first() {
+    let app = spawn_app().await;
+    for name in ["first", "second", "third"] {
+        app.post_api_key(
+            &app.test_user.api_key,
+            serde_json::json!({ "name": name, "scopes": [] }),
+        )
+        .await;
+    }
+
+    let first_page = audit_events(&app, &[("limit", "2")]).await;
+    let events = first_page["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["diff"]["name"], "third");
+    assert_eq!(events[1]["diff"]["name"], "second");
+
+    let cursor = first_page["next_cursor"].to_string();
+    let second_page = audit_events(&app, &[("limit", "2"), ("before", &cursor)]).await;
+    let events = second_page["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["diff"]["name"], "first");
+    assert!(second_page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn the_page_size_is_bounded() {
+    let app = spawn_app().await;
+
+    for limit in ["0", "201"] {
+        let response = app.get_audit_events(&[("limit", limit)]).await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn only_owners_can_read_the_audit_log() {
+    let app = spawn_app().await;
+    let publisher = app.store_user_with_role("publisher").await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/audit", &app.addr))
+        .bearer_auth(&publisher.api_key)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn audit_events_cannot_be_changed_or_deleted() {
+    let app = spawn_app().await;
+    app.post_api_key(
+        &app.test_user.api_key,
+        serde_json::json!({ "name": "ci", "scopes": [] }),
+    )
+    .await;
+
+    let update = sqlx::query!("update audit_events set actor_id = null")
+        .execute(&app.pool)
+        .await;
+    let delete = sqlx::query!("delete from audit_events")
+        .execute(&app.pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
@@ -0,0 +1,144 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, BatchResponder, EmailResponder};
+
+#[tokio::test]
+async fn deliveries_are_empty_for_an_unknown_recipient() {
+    let app = spawn_app().await;
+
+    let response = app.get_deliveries("nobody@example.com").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let deliveries: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert!(deliveries.is_empty());
+}
+
+#[tokio::test]
+async fn deliveries_require_an_email_address() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/deliveries", &app.addr))
+        .bearer_auth(&app.test_user.api_key)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_recorded_with_its_provider_message_id() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(EmailResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let response = app.get_deliveries("ARUN@arun.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let deliveries: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["recipient"], "arun@arun.com");
+    assert_eq!(deliveries[0]["template"], "subscription_confirmation");
+    assert_eq!(deliveries[0]["status"], "sent");
+    assert!(deliveries[0]["provider_message_id"].is_string());
+    assert!(deliveries[0]["error"].is_null());
+
+    let sent: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(sent["Subject"], deliveries[0]["subject"]);
+}
+
+#[tokio::test]
+async fn a_failed_confirmation_email_is_recorded_as_failed() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    let deliveries: Vec<serde_json::Value> = app
+        .get_deliveries("arun@arun.com")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["status"], "failed");
+    assert!(deliveries[0]["provider_message_id"].is_null());
+    assert!(deliveries[0]["error"].is_string());
+}
+
+#[tokio::test]
+async fn every_newsletter_issue_sent_to_a_subscriber_is_recorded() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(EmailResponder)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Issue #1",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let deliveries: Vec<serde_json::Value> = app
+        .get_deliveries("arun@arun.com")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 2);
+    let issue = deliveries
+        .iter()
+        .find(|d| d["template"] == "newsletter_issue")
+        .expect("The newsletter issue was not recorded");
+    assert_eq!(issue["subject"], "Issue #1");
+    assert_eq!(issue["status"], "sent");
+    assert!(issue["provider_message_id"].is_string());
+}
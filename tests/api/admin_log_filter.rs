@@ -0,0 +1,76 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn an_owner_can_change_the_log_filter_at_runtime() {
+    let app = spawn_app().await;
+    let api_key = &app.test_user.api_key;
+
+    let response = app.put_log_filter(api_key, "info,zero2prod=debug").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let changed: serde_json::Value = response.json().await.unwrap();
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/log_filter", &app.addr))
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let current: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(current, changed);
+    assert!(current["filter"]
+        .as_str()
+        .unwrap()
+        .contains("zero2prod=debug"));
+
+    let response = app.put_log_filter(api_key, "info").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn log_filter_changes_are_audited() {
+    let app = spawn_app().await;
+
+    app.put_log_filter(&app.test_user.api_key, "info")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let page: serde_json::Value = app
+        .get_audit_events(&[("action", "log_filter.changed")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let events = page["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["diff"]["filter"]["to"], "info");
+}
+
+#[tokio::test]
+async fn an_invalid_log_filter_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .put_log_filter(&app.test_user.api_key, "zero2prod=shouting")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn only_owners_can_change_the_log_filter() {
+    let app = spawn_app().await;
+
+    for role in ["viewer", "editor", "publisher"] {
+        let user = app.store_user_with_role(role).await;
+        let response = app.put_log_filter(&user.api_key, "debug").await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            403,
+            "Unexpected status for a {}",
+            role
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use zero2prod::domain::SubscriptionStatus::{self, Confirmed, PendingConfirmation};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn store_subscriber(
+    app: &TestApp,
+    name: &str,
+    email: &str,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+) {
+    sqlx::query!(
+        "insert into subscriptions (id, email, name, subscribed_at, status) values ($1, $2, $3, $4, $5)",
+        Uuid::new_v4(),
+        email,
+        name,
+        subscribed_at,
+        status as SubscriptionStatus
+    )
+    .execute(&app.pool)
+    .await
+    .expect("Failed to store the subscriber");
+}
+
+/// Five subscribers, one day apart, the oldest first.
+async fn store_subscribers(app: &TestApp) {
+    let now = Utc::now();
+    let subscribers = [
+        ("Ursula Le Guin", "ursula@example.com", Confirmed),
+        ("Octavia Butler", "octavia@example.com", PendingConfirmation),
+        ("Iain Banks", "iain@culture.org", Confirmed),
+        ("Ann Leckie", "ann@example.com", Confirmed),
+        ("Ted Chiang", "ted_chiang@example.com", PendingConfirmation),
+    ];
+    for (days_ago, (name, email, status)) in subscribers.iter().enumerate().map(|(i, s)| (5 - i, s))
+    {
+        store_subscriber(
+            app,
+            name,
+            email,
+            *status,
+            now - Duration::days(days_ago as i64),
+        )
+        .await;
+    }
+}
+
+async fn list(app: &TestApp, query: &[(&str, &str)]) -> serde_json::Value {
+    let response = app.get_subscribers(&app.test_user.api_key, query).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_newest_first_by_default() {
+    let app = spawn_app().await;
+    store_subscribers(&app).await;
+
+    let page = list(&app, &[]).await;
+
+    assert_eq!(page["total"], 5);
+    assert_eq!(
+        emails(&page),
+        vec![
+            "ted_chiang@example.com",
+            "ann@example.com",
+            "iain@culture.org",
+            "octavia@example.com",
+            "ursula@example.com",
+        ]
+    );
+    assert!(page["next_cursor"].is_null());
+    let subscriber = &page["subscribers"][0];
+    assert_eq!(subscriber["name"], "Ted Chiang");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+    assert!(subscriber["id"].is_string());
+    assert!(subscriber["subscribed_at"].is_string());
+}
+
+#[tokio::test]
+async fn pages_follow_on_from_the_cursor() {
+    let app = spawn_app().await;
+    store_subscribers(&app).await;
+    let query = [("sort", "email"), ("order", "asc"), ("limit", "2")];
+
+    let mut seen = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut query = query.to_vec();
+        if let Some(cursor) = &cursor {
+            query.push(("cursor", cursor));
+        }
+        let page = list(&app, &query).await;
+        assert_eq!(page["total"], 5);
+        seen.extend(emails(&page).into_iter().map(String::from));
+        match page["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+
+    assert_eq!(
+        seen,
+        vec![
+            "ann@example.com",
+            "iain@culture.org",
+            "octavia@example.com",
+            "ted_chiang@example.com",
+            "ursula@example.com",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_signup_date() {
+    let app = spawn_app().await;
+    store_subscribers(&app).await;
+    let after = (Utc::now() - Duration::days(4) - Duration::hours(1)).to_rfc3339();
+    let before = (Utc::now() - Duration::days(1) - Duration::hours(1)).to_rfc3339();
+
+    let page = list(
+        &app,
+        &[
+            ("status", "confirmed"),
+            ("subscribed_after", &after),
+            ("subscribed_before", &before),
+        ],
+    )
+    .await;
+
+    assert_eq!(page["total"], 2);
+    assert_eq!(emails(&page), vec!["ann@example.com", "iain@culture.org"]);
+}
+
+#[tokio::test]
+async fn search_is_a_case_insensitive_substring_of_name_or_email() {
+    let app = spawn_app().await;
+    store_subscribers(&app).await;
+
+    let by_name = list(&app, &[("search", "BUTLER")]).await;
+    assert_eq!(emails(&by_name), vec!["octavia@example.com"]);
+
+    let by_email = list(&app, &[("search", "Culture.ORG")]).await;
+    assert_eq!(emails(&by_email), vec!["iain@culture.org"]);
+
+    // `_` is matched literally rather than as a wildcard, so "Iain Banks" is not a match.
+    let literal = list(&app, &[("search", "n_b")]).await;
+    assert_eq!(literal["total"], 0);
+    let underscore = list(&app, &[("search", "d_c")]).await;
+    assert_eq!(emails(&underscore), vec!["ted_chiang@example.com"]);
+}
+
+#[tokio::test]
+async fn invalid_listing_requests_are_rejected() {
+    let app = spawn_app().await;
+    store_subscribers(&app).await;
+    let page = list(&app, &[("sort", "name"), ("limit", "1")]).await;
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    let test_cases = vec![
+        (
+            vec![("status", "unsubscribed_forever")],
+            "an unknown status",
+        ),
+        (vec![("sort", "password")], "an unknown sort field"),
+        (vec![("order", "sideways")], "an unknown order"),
+        (vec![("limit", "0")], "an empty page"),
+        (vec![("cursor", "not-a-cursor")], "a malformed cursor"),
+        (
+            vec![("sort", "email"), ("cursor", cursor)],
+            "a cursor for another sort",
+        ),
+    ];
+    for (query, description) in test_cases {
+        let response = app.get_subscribers(&app.test_user.api_key, &query).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject a listing with {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn viewers_cannot_list_subscribers_but_editors_can() {
+    let app = spawn_app().await;
+    let viewer = app.store_user_with_role("viewer").await;
+    let editor = app.store_user_with_role("editor").await;
+
+    let response = app.get_subscribers(&viewer.api_key, &[]).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.get_subscribers(&editor.api_key, &[]).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn listing_subscribers_is_audited() {
+    let app = spawn_app().await;
+
+    list(&app, &[("search", "ursula"), ("status", "confirmed")]).await;
+
+    let page: serde_json::Value = app
+        .get_audit_events(&[("action", "subscribers.listed")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let diff = &page["events"][0]["diff"];
+    assert_eq!(diff["filters"], serde_json::json!(["status", "search"]));
+    assert_eq!(diff["sort"], "subscribed_at");
+    assert!(!diff.to_string().contains("ursula"));
+}
@@ -0,0 +1,161 @@
+use crate::helpers::spawn_app;
+
+fn newsletter() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Issue #1",
+        "content": { "text": "Plain text", "html": "<p>HTML</p>" }
+    })
+}
+
+#[tokio::test]
+async fn only_publishers_and_owners_can_publish_newsletters() {
+    let app = spawn_app().await;
+
+    for (role, expected_status) in [
+        ("viewer", 403),
+        ("editor", 403),
+        ("publisher", 200),
+        ("owner", 200),
+    ] {
+        let user = app.store_user_with_role(role).await;
+        let response = reqwest::Client::new()
+            .post(format!("{}/newsletters", &app.addr))
+            .bearer_auth(&user.api_key)
+            .json(&newsletter())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.status().as_u16(),
+            expected_status,
+            "Unexpected status for a {}",
+            role
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_viewer_can_manage_their_own_keys() {
+    let app = spawn_app().await;
+    let viewer = app.store_user_with_role("viewer").await;
+
+    let response = app.get_api_keys(&viewer.api_key).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn viewers_cannot_look_up_deliveries_but_editors_can() {
+    let app = spawn_app().await;
+
+    for (role, expected_status) in [("viewer", 403), ("editor", 200)] {
+        let user = app.store_user_with_role(role).await;
+        let response = reqwest::Client::new()
+            .get(format!("{}/admin/deliveries", &app.addr))
+            .query(&[("email", "nobody@example.com")])
+            .bearer_auth(&user.api_key)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.status().as_u16(),
+            expected_status,
+            "Unexpected status for a {}",
+            role
+        );
+    }
+}
+
+#[tokio::test]
+async fn an_owner_can_create_users_and_change_their_role() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/admin/users", &app.addr))
+        .bearer_auth(&app.test_user.api_key)
+        .json(&serde_json::json!({ "username": "ursula", "role": "viewer" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let user: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(user["role"], "viewer");
+
+    let response = client
+        .put(format!(
+            "{}/admin/users/{}/role",
+            &app.addr,
+            user["user_id"].as_str().unwrap()
+        ))
+        .bearer_auth(&app.test_user.api_key)
+        .json(&serde_json::json!({ "role": "publisher" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("select role from users where username = 'ursula'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.role, "publisher");
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    let app = spawn_app().await;
+    let publisher = app.store_user_with_role("publisher").await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/users", &app.addr))
+        .bearer_auth(&publisher.api_key)
+        .json(&serde_json::json!({ "username": "mallory", "role": "owner" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn an_owner_cannot_change_their_own_role() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .put(format!(
+            "{}/admin/users/{}/role",
+            &app.addr, app.test_user.user_id
+        ))
+        .bearer_auth(&app.test_user.api_key)
+        .json(&serde_json::json!({ "role": "viewer" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unknown_roles_and_taken_usernames_are_rejected() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let create = |body: serde_json::Value| {
+        client
+            .post(format!("{}/admin/users", &app.addr))
+            .bearer_auth(&app.test_user.api_key)
+            .json(&body)
+            .send()
+    };
+
+    let response = create(serde_json::json!({ "username": "ursula", "role": "admin" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = create(serde_json::json!({ "username": "admin", "role": "viewer" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+}
@@ -0,0 +1,223 @@
+use chrono::{Duration, Utc};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn issue_key(app: &TestApp, scopes: &[&str]) -> serde_json::Value {
+    let response = app
+        .post_api_key(
+            &app.test_user.api_key,
+            serde_json::json!({ "name": "integration", "scopes": scopes }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    response.json().await.unwrap()
+}
+
+fn newsletter() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Issue #1",
+        "content": { "text": "Plain text", "html": "<p>HTML</p>" }
+    })
+}
+
+async fn publish_with(app: &TestApp, api_key: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.addr))
+        .json(&newsletter());
+    if let Some(api_key) = api_key {
+        request = request.bearer_auth(api_key);
+    }
+    request.send().await.expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn requests_without_an_api_key_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = publish_with(&app, None).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+}
+
+#[tokio::test]
+async fn requests_with_an_unknown_api_key_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = publish_with(&app, Some("zp_not-a-real-key")).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn requests_with_a_key_missing_the_scope_are_forbidden() {
+    let app = spawn_app().await;
+    let issued = issue_key(&app, &["subscribers:read"]).await;
+    let key = issued["key"].as_str().unwrap();
+
+    let response = publish_with(&app, Some(key)).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/deliveries", &app.addr))
+        .query(&[("email", "nobody@example.com")])
+        .bearer_auth(key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_issued_key_is_listed_without_its_secret() {
+    let app = spawn_app().await;
+    let issued = issue_key(&app, &["newsletters:publish"]).await;
+    let key = issued["key"].as_str().unwrap();
+
+    let response = app.get_api_keys(&app.test_user.api_key).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    assert!(!body.contains(key));
+
+    let keys: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+    let listed = keys
+        .iter()
+        .find(|k| k["id"] == issued["id"])
+        .expect("The issued key was not listed");
+    assert_eq!(listed["name"], "integration");
+    assert_eq!(listed["scopes"], serde_json::json!(["newsletters:publish"]));
+    assert!(key.starts_with(listed["key_prefix"].as_str().unwrap()));
+    assert!(listed["last_used_at"].is_null());
+
+    let stored = sqlx::query!(
+        "select user_id, key_hash from api_keys where id = $1",
+        uuid::Uuid::parse_str(issued["id"].as_str().unwrap()).unwrap()
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(stored.user_id, app.test_user.user_id);
+    assert!(!stored.key_hash.contains(key));
+}
+
+#[tokio::test]
+async fn using_a_key_records_when_it_was_last_used() {
+    let app = spawn_app().await;
+    let issued = issue_key(&app, &["newsletters:publish"]).await;
+
+    publish_with(&app, Some(issued["key"].as_str().unwrap())).await;
+
+    let keys: Vec<serde_json::Value> = app
+        .get_api_keys(&app.test_user.api_key)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let listed = keys.iter().find(|k| k["id"] == issued["id"]).unwrap();
+    assert!(listed["last_used_at"].is_string());
+}
+
+#[tokio::test]
+async fn a_revoked_key_is_rejected() {
+    let app = spawn_app().await;
+    let issued = issue_key(&app, &["newsletters:publish"]).await;
+    let key = issued["key"].as_str().unwrap();
+
+    let response = app
+        .delete_api_key(&app.test_user.api_key, issued["id"].as_str().unwrap())
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = publish_with(&app, Some(key)).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .delete_api_key(&app.test_user.api_key, issued["id"].as_str().unwrap())
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn an_expired_key_is_rejected() {
+    let app = spawn_app().await;
+    let issued = issue_key(&app, &["newsletters:publish"]).await;
+    sqlx::query!(
+        "update api_keys set expires_at = $1 where id = $2",
+        Utc::now() - Duration::minutes(1),
+        uuid::Uuid::parse_str(issued["id"].as_str().unwrap()).unwrap()
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = publish_with(&app, Some(issued["key"].as_str().unwrap())).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_key_cannot_be_issued_already_expired_or_with_unknown_scopes() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "name": "expired",
+                "scopes": ["newsletters:publish"],
+                "expires_at": Utc::now() - Duration::hours(1),
+            }),
+            "an expiry in the past",
+        ),
+        (
+            serde_json::json!({ "name": "unknown", "scopes": ["subscribers:delete"] }),
+            "an unknown scope",
+        ),
+        (
+            serde_json::json!({ "name": " ", "scopes": ["newsletters:publish"] }),
+            "an empty name",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_api_key(&app.test_user.api_key, body).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject a key with {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_key_cannot_grant_scopes_it_does_not_hold() {
+    let app = spawn_app().await;
+    let issued = issue_key(&app, &["api_keys:manage"]).await;
+
+    let response = app
+        .post_api_key(
+            issued["key"].as_str().unwrap(),
+            serde_json::json!({ "name": "escalated", "scopes": ["newsletters:publish"] }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn keys_of_other_users_cannot_be_revoked() {
+    let app = spawn_app().await;
+    let issued = issue_key(&app, &["newsletters:publish"]).await;
+    sqlx::query!(
+        "update api_keys set user_id = (select user_id from users where username = 'admin') where id = $1",
+        uuid::Uuid::parse_str(issued["id"].as_str().unwrap()).unwrap()
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .delete_api_key(&app.test_user.api_key, issued["id"].as_str().unwrap())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
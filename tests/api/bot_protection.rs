@@ -0,0 +1,150 @@
+use secrecy::Secret;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::bot_protection::STUB_PASSING_RESPONSE;
+use zero2prod::configuration::{ChallengeProvider, ChallengeSettings};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// Neither a subscriber nor a confirmation email was stored.
+async fn assert_ignored(app: &TestApp, response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 200);
+    let (subscribers,): (i64,) = sqlx::query_as("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    let (emails,): (i64,) = sqlx::query_as("SELECT count(*) FROM email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!((subscribers, emails), (0, 0));
+}
+
+async fn assert_subscribed(app: &TestApp, response: reqwest::Response) {
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+}
+
+fn challenge(provider: ChallengeProvider, verify_url: Option<String>) -> ChallengeSettings {
+    ChallengeSettings {
+        provider,
+        site_key: "site-key".to_string(),
+        secret_key: Secret::new("secret-key".to_string()),
+        verify_url,
+    }
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_is_ignored() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription(format!("{}&website=spam.example.com", BODY))
+        .await;
+
+    assert_ignored(&app, response).await;
+}
+
+#[tokio::test]
+async fn a_signup_without_a_form_token_is_ignored() {
+    let app = spawn_app().await;
+
+    let response = app.post_raw_subscription(BODY.to_string()).await;
+
+    assert_ignored(&app, response).await;
+}
+
+#[tokio::test]
+async fn a_form_submitted_as_soon_as_it_was_loaded_is_ignored() {
+    let app = spawn_app().await;
+    let form: serde_json::Value = reqwest::get(format!("{}/subscribe/form", &app.addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let response = app
+        .post_raw_subscription(format!(
+            "{}&form_started={}",
+            BODY,
+            form["form_started"].as_str().unwrap()
+        ))
+        .await;
+
+    assert_ignored(&app, response).await;
+}
+
+#[tokio::test]
+async fn a_configured_challenge_must_be_solved() {
+    let app = spawn_app_with(|c| {
+        c.bot_protection.challenge = Some(challenge(ChallengeProvider::Stub, None))
+    })
+    .await;
+
+    let response = app
+        .post_subscription(format!("{}&challenge_response=wrong", BODY))
+        .await;
+    assert_ignored(&app, response).await;
+
+    let response = app
+        .post_subscription(format!(
+            "{}&challenge_response={}",
+            BODY, STUB_PASSING_RESPONSE
+        ))
+        .await;
+    assert_subscribed(&app, response).await;
+}
+
+#[tokio::test]
+async fn hcaptcha_responses_are_checked_with_the_provider() {
+    let hcaptcha = MockServer::start().await;
+    let verify_url = format!("{}/siteverify", hcaptcha.uri());
+    let app = spawn_app_with(|c| {
+        c.bot_protection.challenge = Some(challenge(ChallengeProvider::Hcaptcha, Some(verify_url)))
+    })
+    .await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(body_string_contains("secret=secret-key"))
+        .and(body_string_contains("response=solved-by-a-person"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .expect(1)
+        .mount(&hcaptcha)
+        .await;
+
+    // Plain forms post the field the hCaptcha widget adds.
+    let response = app
+        .post_subscription(format!("{}&h-captcha-response=solved-by-a-person", BODY))
+        .await;
+
+    assert_subscribed(&app, response).await;
+}
+
+#[tokio::test]
+async fn the_widget_is_told_which_challenge_to_render() {
+    let app = spawn_app_with(|c| {
+        c.bot_protection.challenge = Some(challenge(ChallengeProvider::Turnstile, None))
+    })
+    .await;
+
+    let form: serde_json::Value = reqwest::get(format!("{}/subscribe/form", &app.addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        form["challenge"],
+        serde_json::json!({ "provider": "turnstile", "site_key": "site-key" })
+    );
+}
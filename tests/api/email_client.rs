@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use claim::{assert_matches, assert_ok};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::lorem::en::{Paragraph, Sentence};
+use fake::{Fake, Faker};
+use secrecy::Secret;
+use wiremock::matchers::{any, header, header_exists, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::{BatchSendError, Email, EmailClient, MAX_BATCH_SIZE};
+
+use crate::helpers::BatchResponder;
+
+fn email() -> SubscriberEmail {
+    SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+}
+
+fn batch_email() -> Email {
+    Email {
+        recipient: email(),
+        subject: Sentence(1..2).fake(),
+        html_content: Paragraph(1..10).fake(),
+        text_content: Paragraph(1..10).fake(),
+    }
+}
+
+fn email_client(base_url: String) -> EmailClient {
+    EmailClient::new(
+        base_url,
+        Secret::new(Faker.fake()),
+        email(),
+        Duration::from_millis(200),
+    )
+}
+
+#[tokio::test]
+async fn send_email_batch_fires_a_request_to_the_batch_endpoint() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(header_exists("X-Postmark-Server-Token"))
+        .and(header("Content-Type", "application/json"))
+        .and(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder)
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcomes = email_client
+        .send_email_batch(&[batch_email(), batch_email()])
+        .await;
+
+    assert_eq!(outcomes.len(), 2);
+    for outcome in outcomes {
+        let response = assert_ok!(outcome);
+        assert!(response.message_id.is_some());
+    }
+}
+
+#[tokio::test]
+async fn send_email_batch_groups_messages_up_to_the_batch_limit() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(path("/email/batch"))
+        .respond_with(BatchResponder)
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+
+    let emails: Vec<Email> = (0..MAX_BATCH_SIZE + 1).map(|_| batch_email()).collect();
+    let outcomes = email_client.send_email_batch(&emails).await;
+
+    assert_eq!(outcomes.len(), MAX_BATCH_SIZE + 1);
+    assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
+
+    let requests = mock_server.received_requests().await.unwrap();
+    let batch_sizes: Vec<usize> = requests
+        .iter()
+        .map(|r| {
+            serde_json::from_slice::<Vec<serde_json::Value>>(&r.body)
+                .unwrap()
+                .len()
+        })
+        .collect();
+    assert_eq!(batch_sizes, vec![MAX_BATCH_SIZE, 1]);
+}
+
+#[tokio::test]
+async fn send_email_batch_reports_rejections_for_individual_messages() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    let response_body = serde_json::json!([
+        {
+            "ErrorCode": 0,
+            "Message": "OK",
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "SubmittedAt": "2023-02-01T10:00:00.0000000-05:00",
+            "To": "receiver1@example.com"
+        },
+        {
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        }
+    ]);
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcomes = email_client
+        .send_email_batch(&[batch_email(), batch_email()])
+        .await;
+
+    assert_ok!(&outcomes[0]);
+    assert_matches!(
+        &outcomes[1],
+        Err(BatchSendError::Rejected {
+            error_code: 406,
+            ..
+        })
+    );
+}
+
+#[tokio::test]
+async fn send_email_batch_treats_a_2xx_with_a_malformed_body_as_delivered() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcomes = email_client
+        .send_email_batch(&[batch_email(), batch_email()])
+        .await;
+
+    assert_eq!(outcomes.len(), 2);
+    for outcome in outcomes {
+        let response = assert_ok!(outcome);
+        assert!(response.message_id.is_none());
+    }
+}
+
+#[tokio::test]
+async fn send_email_batch_treats_messages_missing_from_a_2xx_response_as_delivered() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    let response_body = serde_json::json!([
+        {
+            "ErrorCode": 0,
+            "Message": "OK",
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"
+        }
+    ]);
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcomes = email_client
+        .send_email_batch(&[batch_email(), batch_email()])
+        .await;
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(outcomes.iter().all(|outcome| outcome.is_ok()));
+}
+
+#[tokio::test]
+async fn send_email_batch_fails_every_message_if_the_server_returns_500() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcomes = email_client
+        .send_email_batch(&[batch_email(), batch_email()])
+        .await;
+
+    assert_eq!(outcomes.len(), 2);
+    for outcome in outcomes {
+        assert_matches!(outcome, Err(BatchSendError::RequestFailed(_)));
+    }
+}
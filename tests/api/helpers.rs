@@ -1,34 +1,141 @@
-use std::net::TcpListener;
-
-use actix_web::HttpResponse;
 use once_cell::sync::Lazy;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use secrecy::Secret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{MockServer, Request, Respond, ResponseTemplate};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    authentication::{issue_api_key, NewApiKey, Scope},
+    bot_protection::FormToken,
+    configuration::{
+        get_configuration, ConfirmationLinkSettings, DatabaseSettings, LogFormat, Settings,
+    },
+    confirmation_email::ConfirmationEmailRenderer,
     email_client::EmailClient,
-    startup::{run, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    migrations::MIGRATOR,
+    outbox_dispatcher::{try_execute_task, ExecutionOutcome},
+    reload::SettingsReloader,
+    startup::Application,
+    telemetry::{get_subscriber, init_subscriber, LogFilter},
 };
-static TRACING: Lazy<()> = Lazy::new(|| {
+static TRACING: Lazy<LogFilter> = Lazy::new(|| {
     let default_filter_level = "info".into();
     let subscriber_name = "test".into();
+    // Spans go nowhere, but still carry trace context from and to the services we talk to.
+    let tracer_provider = SdkTracerProvider::builder().build();
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            LogFormat::Bunyan,
+            std::io::stdout,
+            Some(&tracer_provider),
+        );
         init_subscriber(subscriber);
+        log_filter
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            LogFormat::Bunyan,
+            std::io::sink,
+            Some(&tracer_provider),
+        );
         init_subscriber(subscriber);
+        log_filter
     }
 });
 
+/// Stands in for Postmark's single email endpoint, accepting every message it receives.
+pub struct EmailResponder;
+
+impl Respond for EmailResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let message: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "ErrorCode": 0,
+            "Message": "OK",
+            "MessageID": Uuid::new_v4().to_string(),
+            "SubmittedAt": "2023-02-01T10:00:00.0000000-05:00",
+            "To": message["To"],
+        }))
+    }
+}
+
+/// Stands in for Postmark's batch endpoint, accepting every message it receives.
+pub struct BatchResponder;
+
+impl Respond for BatchResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<serde_json::Value> = body
+            .iter()
+            .map(|message| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": Uuid::new_v4().to_string(),
+                    "SubmittedAt": "2023-02-01T10:00:00.0000000-05:00",
+                    "To": message["To"],
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
 pub struct TestApp {
     pub addr: String,
     pub port: u16,
+    pub http_redirect_port: Option<u16>,
     pub pool: PgPool,
     pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub hmac_secret: Secret<String>,
+    pub confirmation_links: ConfirmationLinkSettings,
+    pub test_user: TestUser,
+    configuration: Settings,
+    settings_reloader: SettingsReloader,
+}
+
+/// A user holding an API key with every scope.
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub api_key: String,
+}
+
+impl TestUser {
+    pub async fn store(pool: &PgPool, hmac_secret: &Secret<String>, role: &str) -> TestUser {
+        let user_id = Uuid::new_v4();
+        sqlx::query!(
+            "insert into users (user_id, username, role) values ($1, $2, $3)",
+            user_id,
+            Uuid::new_v4().to_string(),
+            role
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store the test user");
+
+        let api_key = NewApiKey::generate();
+        issue_api_key(
+            pool,
+            hmac_secret,
+            user_id,
+            "test",
+            &Scope::ALL,
+            None,
+            &api_key,
+        )
+        .await
+        .expect("Failed to issue the test user's API key");
+
+        TestUser {
+            user_id,
+            api_key: api_key.expose().to_string(),
+        }
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -37,7 +144,40 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        let renderer = ConfirmationEmailRenderer::new(
+            self.configuration.application.base_url.clone(),
+            self.hmac_secret.clone(),
+            self.settings_reloader.confirmation_links(),
+        );
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.pool, &self.email_client, &renderer)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// A token for a form loaded long enough ago to pass the minimum fill time.
+    pub fn form_started(&self) -> String {
+        FormToken::issue(
+            &self.hmac_secret,
+            chrono::Utc::now() - chrono::Duration::seconds(10),
+        )
+        .as_ref()
+        .to_string()
+    }
+
+    /// Posts `body` as a form, filled in at human speed.
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
+        self.post_raw_subscription(format!("{}&form_started={}", body, self.form_started()))
+            .await
+    }
+
+    pub async fn post_raw_subscription(&self, body: String) -> reqwest::Response {
         let address = format!("{}/subscribe", &self.addr);
         println!("Address in post_subscription is : {}", &address);
         reqwest::Client::new()
@@ -49,6 +189,103 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.addr))
+            .bearer_auth(&self.test_user.api_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_deliveries(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/deliveries", &self.addr))
+            .query(&[("email", email)])
+            .bearer_auth(&self.test_user.api_key)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_api_key(&self, api_key: &str, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/api_keys", &self.addr))
+            .bearer_auth(api_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_api_keys(&self, api_key: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/api_keys", &self.addr))
+            .bearer_auth(api_key)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_api_key(&self, api_key: &str, key_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/api_keys/{}", &self.addr, key_id))
+            .bearer_auth(api_key)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_audit_events(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/audit", &self.addr))
+            .query(query)
+            .bearer_auth(&self.test_user.api_key)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_log_filter(&self, api_key: &str, filter: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/log_filter", &self.addr))
+            .bearer_auth(api_key)
+            .json(&serde_json::json!({ "filter": filter }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscribers(
+        &self,
+        api_key: &str,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers", &self.addr))
+            .query(query)
+            .bearer_auth(api_key)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Reloads the settings the app was spawned with, as adjusted by `customise`.
+    pub fn reload_settings(
+        &self,
+        customise: impl FnOnce(&mut Settings),
+    ) -> Result<(), Vec<String>> {
+        let mut configuration = self.configuration.clone();
+        customise(&mut configuration);
+        self.settings_reloader.apply(&configuration)
+    }
+
+    /// Stores another user with `role`, holding a key with every scope.
+    pub async fn store_user_with_role(&self, role: &str) -> TestUser {
+        TestUser::store(&self.pool, &self.hmac_secret, role).await
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -72,8 +309,17 @@ impl TestApp {
     }
 }
 
+pub fn log_filter() -> LogFilter {
+    TRACING.clone()
+}
+
 pub async fn spawn_app() -> TestApp {
-    Lazy::force(&TRACING);
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns the application after letting the test adjust its configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
+    let log_filter = TRACING.clone();
 
     let email_server = MockServer::start().await;
 
@@ -83,31 +329,60 @@ pub async fn spawn_app() -> TestApp {
         conf.database.database_name = database_name;
         conf.application.port = 0;
         conf.email_client.base_url = email_server.uri();
+        customise(&mut conf);
         conf
     };
 
-    let db_pool: PgPool = configure_database(&configuration.database).await;
+    configure_database(&configuration.database).await;
 
-    let application = Application::build(configuration.clone())
+    let application = Application::build(configuration.clone(), log_filter)
         .await
         .expect("Unable to build application");
 
     let port = application.port();
+    let http_redirect_port = application.http_redirect_port();
+    let settings_reloader = application.settings_reloader();
     let address = format!("http://{}:{}", &configuration.application.host, port);
 
     tokio::spawn(application.run_until_stopped());
 
     println!("Address is : {}", address);
 
+    let pool = configuration.database.clone().get_connection_pool();
+    let test_user = TestUser::store(&pool, &configuration.application.hmac_secret, "owner").await;
+
     TestApp {
         addr: address,
-        port: port,
-        pool: configuration.database.get_connection_pool(),
-        email_server: email_server,
+        port,
+        http_redirect_port,
+        pool,
+        email_server,
+        email_client: configuration
+            .email_client
+            .clone()
+            .email_client()
+            .expect("Invalid email client settings"),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        confirmation_links: configuration.confirmation_links.clone(),
+        test_user,
+        configuration,
+        settings_reloader,
     }
 }
 
 pub async fn configure_database(database: &DatabaseSettings) -> PgPool {
+    let db_pool = create_database(database).await;
+
+    MIGRATOR
+        .run(&db_pool)
+        .await
+        .expect("Unablet to migrate the database");
+
+    db_pool
+}
+
+/// Creates an empty database, without running any migration.
+pub async fn create_database(database: &DatabaseSettings) -> PgPool {
     let mut connection = PgConnection::connect_with(&database.without_db())
         .await
         .expect("unable to connect to pg");
@@ -118,14 +393,7 @@ pub async fn configure_database(database: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to create database");
 
-    let db_pool = PgPool::connect_with(database.with_db())
+    PgPool::connect_with(database.with_db())
         .await
-        .expect("Failed to connect to PG");
-
-    sqlx::migrate!("./migrations")
-        .run(&db_pool)
-        .await
-        .expect("Unablet to migrate the database");
-
-    db_pool
+        .expect("Failed to connect to PG")
 }
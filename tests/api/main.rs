@@ -1,4 +1,18 @@
+mod admin_audit;
+mod admin_deliveries;
+mod admin_log_filter;
+mod admin_subscribers;
+mod admin_users;
+mod api_keys;
+mod bot_protection;
+mod email_client;
 mod health_check;
 mod helpers;
+mod migrations;
+mod newsletters;
+mod security_headers;
+mod settings_reload;
+mod signup_widget;
 mod subscriptions;
 pub mod subscriptions_confirm;
+mod tls;
@@ -0,0 +1,76 @@
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::startup::Application;
+
+use crate::helpers::{configure_database, create_database, log_filter};
+
+fn configuration(migrate_on_startup: bool) -> Settings {
+    let mut configuration = get_configuration().expect("Unable to load configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.database.migrate_on_startup = migrate_on_startup;
+    configuration.application.port = 0;
+    configuration
+}
+
+#[tokio::test]
+async fn pending_migrations_are_applied_at_startup_when_enabled() {
+    let log_filter = log_filter();
+    let configuration = configuration(true);
+    let pool = create_database(&configuration.database).await;
+
+    let outcome = Application::build(configuration, log_filter).await;
+
+    assert!(outcome.is_ok());
+    sqlx::query("SELECT count(*) FROM subscriptions")
+        .execute(&pool)
+        .await
+        .expect("The schema was not created");
+}
+
+#[tokio::test]
+async fn startup_is_refused_when_the_schema_is_behind_and_migrations_are_disabled() {
+    let log_filter = log_filter();
+    let configuration = configuration(false);
+    create_database(&configuration.database).await;
+
+    let outcome = Application::build(configuration, log_filter).await;
+
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn startup_is_refused_when_the_schema_is_ahead_of_the_binary() {
+    let log_filter = log_filter();
+    for migrate_on_startup in [true, false] {
+        let configuration = configuration(migrate_on_startup);
+        let pool = configure_database(&configuration.database).await;
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (99990101000000, 'from a newer build', true, '\x00', 0)
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let outcome = Application::build(configuration, log_filter.clone()).await;
+
+        assert!(
+            outcome.is_err(),
+            "Started with migrate_on_startup = {}",
+            migrate_on_startup
+        );
+    }
+}
+
+#[tokio::test]
+async fn startup_accepts_an_up_to_date_schema_without_migrating() {
+    let log_filter = log_filter();
+    let configuration = configuration(false);
+    configure_database(&configuration.database).await;
+
+    let outcome = Application::build(configuration, log_filter).await;
+
+    assert!(outcome.is_ok());
+}
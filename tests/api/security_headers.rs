@@ -0,0 +1,35 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn responses_carry_the_security_headers() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health_check", &app.addr))
+        .await
+        .expect("Failed to execute request");
+
+    let headers = response.headers();
+    assert!(headers["Content-Security-Policy"]
+        .to_str()
+        .unwrap()
+        .contains("frame-ancestors 'none'"));
+    assert_eq!(headers["X-Frame-Options"], "DENY");
+    assert_eq!(headers["Referrer-Policy"], "no-referrer");
+    assert_eq!(headers["X-Content-Type-Options"], "nosniff");
+    // The test configuration serves plain HTTP.
+    assert!(headers.get("Strict-Transport-Security").is_none());
+}
+
+#[tokio::test]
+async fn hsts_is_sent_when_configured() {
+    let app = spawn_app_with(|c| c.security.hsts_max_age_seconds = 600).await;
+
+    let response = reqwest::get(format!("{}/health_check", &app.addr))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(
+        response.headers()["Strict-Transport-Security"],
+        "max-age=600; includeSubDomains"
+    );
+}
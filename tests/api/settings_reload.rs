@@ -0,0 +1,71 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, BatchResponder, TestApp};
+use crate::newsletters::create_confirmed_subscriber;
+
+async fn publish_newsletter(app: &TestApp) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn reloaded_email_settings_are_used_without_a_restart() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let new_email_server = MockServer::start().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder)
+        .expect(1)
+        .mount(&new_email_server)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.reload_settings(|c| c.email_client.base_url = new_email_server.uri())
+        .unwrap();
+
+    publish_newsletter(&app).await;
+}
+
+#[tokio::test]
+async fn an_invalid_reload_is_rejected_and_the_old_settings_stay_active() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let new_email_server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&new_email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let problems = app
+        .reload_settings(|c| {
+            c.email_client.base_url = new_email_server.uri();
+            c.email_client.sender_email = "not-an-email".to_string();
+        })
+        .unwrap_err();
+
+    assert!(problems[0].starts_with("email_client.sender_email"));
+    publish_newsletter(&app).await;
+}
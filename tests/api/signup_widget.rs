@@ -0,0 +1,133 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const MARKETING_SITE: &str = "https://www.example.com";
+
+async fn spawn_app_for_marketing_site() -> TestApp {
+    spawn_app_with(|c| c.application.cors_allowed_origins = vec![MARKETING_SITE.to_string()]).await
+}
+
+async fn post_json_subscription(app: &TestApp, mut body: serde_json::Value) -> reqwest::Response {
+    body["form_started"] = app.form_started().into();
+    reqwest::Client::new()
+        .post(format!("{}/subscribe", &app.addr))
+        .header("Origin", MARKETING_SITE)
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[tokio::test]
+async fn the_widget_is_served_as_javascript() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/embed/signup.js", &app.addr))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/javascript; charset=utf-8"
+    );
+    assert!(response.text().await.unwrap().contains("/subscribe"));
+}
+
+#[tokio::test]
+async fn the_widget_fetches_a_fresh_form_token() {
+    let app = spawn_app_for_marketing_site().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscribe/form", &app.addr))
+        .header("Origin", MARKETING_SITE)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        MARKETING_SITE
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["form_started"].is_string());
+    assert!(body["challenge"].is_null());
+}
+
+#[tokio::test]
+async fn allowed_origins_pass_the_preflight_check() {
+    let app = spawn_app_for_marketing_site().await;
+
+    let response = reqwest::Client::new()
+        .request(reqwest::Method::OPTIONS, format!("{}/subscribe", &app.addr))
+        .header("Origin", MARKETING_SITE)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        MARKETING_SITE
+    );
+}
+
+#[tokio::test]
+async fn other_origins_fail_the_preflight_check() {
+    let app = spawn_app_for_marketing_site().await;
+
+    let response = reqwest::Client::new()
+        .request(reqwest::Method::OPTIONS, format!("{}/subscribe", &app.addr))
+        .header("Origin", "https://elsewhere.example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert!(response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .is_none());
+}
+
+#[tokio::test]
+async fn a_json_signup_records_the_origin_it_came_from() {
+    let app = spawn_app_for_marketing_site().await;
+
+    let response = post_json_subscription(
+        &app,
+        serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        MARKETING_SITE
+    );
+    let saved = sqlx::query!("SELECT email, signup_origin FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.signup_origin.as_deref(), Some(MARKETING_SITE));
+}
+
+#[tokio::test]
+async fn invalid_json_signups_get_an_error_for_each_field() {
+    let app = spawn_app_for_marketing_site().await;
+
+    let response = post_json_subscription(
+        &app,
+        serde_json::json!({ "name": "", "email": "not-an-email" }),
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["errors"]["name"].is_string());
+    assert!(body["errors"]["email"].is_string());
+}
@@ -1,19 +1,28 @@
+use chrono::{Duration, Utc};
 use linkify::LinkKind;
+use uuid::Uuid;
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::{spawn_app, TestApp};
+use zero2prod::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use zero2prod::outbox_dispatcher::{
+    purge_abandoned_emails, OutboxEmail, ABANDONED_EMAIL_RETENTION, MAX_ATTEMPTS,
+};
+use zero2prod::subscriber_repository::{
+    AlreadySubscribed, PendingSubscriber, PostgresSubscriberRepository, SubscriberRepository,
+};
+
+use crate::helpers::{spawn_app, EmailResponder};
 
 #[tokio::test]
 async fn subscribe_returns_200_for_valid_form_data() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
     let body = "name=arun%20manivannan&email=arun%40arun.com";
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(EmailResponder)
         .mount(&app.email_server)
         .await;
 
@@ -31,7 +40,6 @@ async fn subscribe_returns_200_for_valid_form_data() {
 #[tokio::test]
 async fn subscribe_returns_400_for_missing_name_or_email() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
     let body = [
         ("name=arun%20manivannan", "missing email"),
         ("email=arun%40arun.com", "missing name"),
@@ -54,7 +62,6 @@ async fn subscribe_returns_400_for_missing_name_or_email() {
 #[tokio::test]
 async fn subscribe_returns_a_200_when_fields_are_present_but_empty() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
 
     let test_cases = vec![
         ("name=&email=ursula%40gmail.com", "empty name"),
@@ -77,17 +84,17 @@ async fn subscribe_returns_a_200_when_fields_are_present_but_empty() {
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
     let body = "name=arun%20manivannan&email=arun%40arun.com";
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(EmailResponder)
         .expect(1)
         .mount(&app.email_server)
         .await;
 
     let response = app.post_subscription(body.to_string()).await;
+    app.dispatch_all_pending_emails().await;
 
     assert_eq!(200, response.status().as_u16());
 }
@@ -99,12 +106,13 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(EmailResponder)
         .expect(1)
         .mount(&app.email_server)
         .await;
 
     app.post_subscription(body.to_string()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
@@ -121,10 +129,152 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         links[0].as_str().to_string()
     };
 
-    let html_link = get_link(&body["HtmlBody".to_string()].as_str().unwrap());
-    let text_link = get_link(&body["TextBody".to_string()].as_str().unwrap());
+    let html_link = get_link(body["HtmlBody".to_string()].as_str().unwrap());
+    let text_link = get_link(body["TextBody".to_string()].as_str().unwrap());
 
     println!("Html Link :::: {}", &html_link);
 
     assert_eq!(html_link, text_link);
 }
+
+#[tokio::test]
+async fn subscribe_does_not_wait_for_the_email_provider() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.to_string()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let queued = sqlx::query!("select recipient, template from email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .expect("Failed to fetch the queued confirmation email");
+    assert_eq!(queued.recipient, "arun@arun.com");
+    assert_eq!(queued.template, "subscription_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_does_not_store_the_subscriber_if_the_email_cannot_be_queued() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    sqlx::query!("ALTER TABLE email_outbox DROP COLUMN subject;")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscription(body.to_string()).await;
+
+    assert_eq!(500, response.status().as_u16());
+    let subscribers = sqlx::query!("select count(*) as count from subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, Some(0));
+}
+
+#[tokio::test]
+async fn a_failed_confirmation_email_is_retried() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(EmailResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.to_string()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("select attempts, last_error from email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.attempts, 1);
+    assert!(queued.last_error.is_some());
+
+    // Skip the backoff period
+    sqlx::query!("update email_outbox set next_attempt_at = now()")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("select count(*) as count from email_outbox")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(0));
+}
+
+#[tokio::test]
+async fn abandoned_emails_are_purged_once_the_retention_period_is_over() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+    app.post_subscription(body.to_string()).await;
+
+    sqlx::query!(
+        "update email_outbox set attempts = $1, created_at = $2",
+        MAX_ATTEMPTS,
+        Utc::now() - ABANDONED_EMAIL_RETENTION + Duration::hours(1)
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(purge_abandoned_emails(&app.pool).await.unwrap(), 0);
+
+    sqlx::query!(
+        "update email_outbox set created_at = $1",
+        Utc::now() - ABANDONED_EMAIL_RETENTION - Duration::hours(1)
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(purge_abandoned_emails(&app.pool).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn a_repeated_signup_is_reported_without_the_email_address() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+    app.post_subscription(body.to_string()).await;
+
+    let repository = PostgresSubscriberRepository::new(app.pool.clone());
+    let subscriber = NewSubscriber {
+        email: SubscriberEmail::parse("arun@arun.com".to_string()).unwrap(),
+        name: SubscriberName::parse("arun manivannan".to_string()).unwrap(),
+    };
+    let error = repository
+        .add_pending_subscriber(PendingSubscriber {
+            id: Uuid::new_v4(),
+            subscriber: &subscriber,
+            signup_origin: None,
+            confirmation_email: OutboxEmail {
+                recipient: "arun@arun.com",
+                template: "subscription_confirmation",
+                subject: "Welcome !",
+                subscriber_id: Uuid::new_v4(),
+            },
+        })
+        .await
+        .unwrap_err();
+
+    assert!(error.is::<AlreadySubscribed>());
+    assert!(!format!("{:?}", error).contains("arun@arun.com"));
+}
@@ -1,10 +1,17 @@
+use chrono::Duration;
 use reqwest::Url;
+use secrecy::Secret;
+use uuid::Uuid;
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::configuration::{ConfirmationLinkMode, SigningKey};
+use zero2prod::domain::{SignedConfirmation, SubscriptionStatus, SubscriptionToken};
+use zero2prod::routes::subscriptions::rehash_legacy_subscription_tokens;
+
+use crate::helpers::{spawn_app, spawn_app_with, EmailResponder, TestApp};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_400() {
@@ -24,11 +31,12 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(EmailResponder)
         .mount(&app.email_server)
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
 
@@ -46,11 +54,12 @@ async fn clicking_on_the_confirmation_link_confirms_subscriber() {
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(EmailResponder)
         .mount(&app.email_server)
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
 
@@ -62,12 +71,411 @@ async fn clicking_on_the_confirmation_link_confirms_subscriber() {
         .error_for_status()
         .unwrap();
 
-    let saved = sqlx::query!(r#"select email, name, status from subscriptions"#)
-        .fetch_one(&app.pool)
-        .await
-        .expect("Failed to fetch saved subscription");
+    let saved = sqlx::query!(
+        r#"select email, name, status as "status: SubscriptionStatus" from subscriptions"#
+    )
+    .fetch_one(&app.pool)
+    .await
+    .expect("Failed to fetch saved subscription");
 
     assert_eq!(saved.email, "arun@arun.com");
     assert_eq!(saved.name, "arun manivannan");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn confirmations_with_a_malformed_token_are_rejected_with_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-token",
+        &app.addr,
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        &app.addr,
+        SubscriptionToken::generate().as_ref()
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_not_stored_in_plaintext() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(EmailResponder)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.text);
+    let (_, token) = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap();
+
+    let saved =
+        sqlx::query!("select subscription_token, subscription_token_hash from subscription_tokens")
+            .fetch_one(&app.pool)
+            .await
+            .expect("Failed to fetch saved subscription token");
+
+    assert!(saved.subscription_token.is_none());
+    let hash = saved.subscription_token_hash.unwrap();
+    assert_ne!(hash, token);
+    assert!(!hash.contains(token.as_ref()));
+}
+
+#[tokio::test]
+async fn the_outbox_never_holds_a_subscription_token() {
+    let app = spawn_app().await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let (_, token) = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap();
+    let outbox = sqlx::query!(r#"select to_jsonb(email_outbox)::text as "row!" from email_outbox"#)
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.len(), 1);
+    assert!(!outbox[0].row.contains(token.as_ref()));
+
+    // The link in an email that was not delivered does not confirm anything either.
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn legacy_plaintext_tokens_are_rehashed_and_still_confirm_the_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+    let legacy_token = SubscriptionToken::generate();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'legacy@arun.com', 'legacy subscriber', now(), 'pending_confirmation')
+        "#,
+        subscriber_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2), ('malformed', $2)"#,
+        legacy_token.as_ref(),
+        subscriber_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let rehashed = rehash_legacy_subscription_tokens(&app.pool, &app.hmac_secret)
+        .await
+        .unwrap();
+    assert_eq!(rehashed, 2);
+
+    let remaining = sqlx::query!("select subscription_token from subscription_tokens")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert!(remaining[0].subscription_token.is_none());
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        &app.addr,
+        legacy_token.as_ref()
+    ))
+    .await
+    .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!(
+        r#"select status as "status: SubscriptionStatus" from subscriptions where id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+fn signing_key(id: &str, secret: &str) -> SigningKey {
+    SigningKey {
+        id: id.to_string(),
+        secret: Secret::new(secret.to_string()),
+    }
+}
+
+async fn spawn_app_with_signed_links(signing_keys: Vec<SigningKey>) -> TestApp {
+    spawn_app_with(|conf| {
+        conf.confirmation_links.mode = ConfirmationLinkMode::Signed;
+        conf.confirmation_links.signing_keys = signing_keys;
+    })
+    .await
+}
+
+async fn create_pending_subscriber(app: &TestApp) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'arun@arun.com', 'arun manivannan', now(), 'pending_confirmation')
+        "#,
+        subscriber_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+fn signed_link(app: &TestApp, confirmation: &SignedConfirmation, key: &SigningKey) -> Url {
+    Url::parse_with_params(
+        &format!("{}/subscriptions/confirm", &app.addr),
+        &[
+            ("subscriber_id", confirmation.subscriber_id.to_string()),
+            ("list", confirmation.list.clone()),
+            ("expires", confirmation.expires_at.to_string()),
+            ("key_id", key.id.clone()),
+            ("signature", confirmation.signature(&key.secret)),
+        ],
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn signed_links_confirm_the_subscriber_without_storing_a_token() {
+    let app = spawn_app_with_signed_links(vec![signing_key("current", "current-secret")]).await;
+    let body = "name=arun%20manivannan&email=arun%40arun.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(EmailResponder)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let tokens = sqlx::query!("select count(*) as count from subscription_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, Some(0));
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert!(confirmation_links
+        .html
+        .query_pairs()
+        .any(|(key, value)| key == "key_id" && value == "current"));
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!(r#"select status as "status: SubscriptionStatus" from subscriptions"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn signed_links_with_a_tampered_claim_are_rejected_with_401() {
+    let key = signing_key("current", "current-secret");
+    let app = spawn_app_with_signed_links(vec![key.clone()]).await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+
+    let confirmation = SignedConfirmation::new(
+        subscriber_id,
+        app.confirmation_links.list.clone(),
+        Duration::hours(1),
+    );
+    let mut link = signed_link(&app, &confirmation, &key);
+    let tampered: Vec<(String, String)> = link
+        .query_pairs()
+        .map(|(k, v)| {
+            let v = if k == "expires" {
+                (confirmation.expires_at + 3600).to_string()
+            } else {
+                v.to_string()
+            };
+            (k.to_string(), v)
+        })
+        .collect();
+    link.query_pairs_mut().clear().extend_pairs(tampered);
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_signed_links_are_rejected_with_401() {
+    let key = signing_key("current", "current-secret");
+    let app = spawn_app_with_signed_links(vec![key.clone()]).await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+
+    let confirmation = SignedConfirmation::new(
+        subscriber_id,
+        app.confirmation_links.list.clone(),
+        Duration::hours(-1),
+    );
+    let response = reqwest::get(signed_link(&app, &confirmation, &key))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn signed_links_for_another_list_are_rejected_with_401() {
+    let key = signing_key("current", "current-secret");
+    let app = spawn_app_with_signed_links(vec![key.clone()]).await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+
+    let confirmation =
+        SignedConfirmation::new(subscriber_id, "another-list".into(), Duration::hours(1));
+    let response = reqwest::get(signed_link(&app, &confirmation, &key))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn signed_links_from_the_previous_key_are_accepted_until_it_is_removed() {
+    let current = signing_key("current", "current-secret");
+    let previous = signing_key("previous", "previous-secret");
+
+    let app = spawn_app_with_signed_links(vec![current.clone(), previous.clone()]).await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+    let confirmation = SignedConfirmation::new(
+        subscriber_id,
+        app.confirmation_links.list.clone(),
+        Duration::hours(1),
+    );
+    let response = reqwest::get(signed_link(&app, &confirmation, &previous))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let app = spawn_app_with_signed_links(vec![current]).await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+    let confirmation = SignedConfirmation::new(
+        subscriber_id,
+        app.confirmation_links.list.clone(),
+        Duration::hours(1),
+    );
+    let response = reqwest::get(signed_link(&app, &confirmation, &previous))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn signed_links_for_an_unknown_subscriber_are_rejected_with_401() {
+    let key = signing_key("current", "current-secret");
+    let app = spawn_app_with_signed_links(vec![key.clone()]).await;
+
+    let confirmation = SignedConfirmation::new(
+        Uuid::new_v4(),
+        app.confirmation_links.list.clone(),
+        Duration::hours(1),
+    );
+    let response = reqwest::get(signed_link(&app, &confirmation, &key))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_cannot_be_confirmed_again() {
+    let key = signing_key("current", "current-secret");
+    let app = spawn_app_with_signed_links(vec![key.clone()]).await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+    sqlx::query!(
+        "update subscriptions set status = $2 where id = $1",
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed as SubscriptionStatus
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let confirmation = SignedConfirmation::new(
+        subscriber_id,
+        app.confirmation_links.list.clone(),
+        Duration::hours(1),
+    );
+    let response = reqwest::get(signed_link(&app, &confirmation, &key))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!(
+        r#"select status as "status: SubscriptionStatus" from subscriptions where id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Unsubscribed);
+}
+
+#[tokio::test]
+async fn confirming_twice_is_harmless() {
+    let key = signing_key("current", "current-secret");
+    let app = spawn_app_with_signed_links(vec![key.clone()]).await;
+    let subscriber_id = create_pending_subscriber(&app).await;
+    let confirmation = SignedConfirmation::new(
+        subscriber_id,
+        app.confirmation_links.list.clone(),
+        Duration::hours(1),
+    );
+
+    for _ in 0..2 {
+        let response = reqwest::get(signed_link(&app, &confirmation, &key))
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
 }
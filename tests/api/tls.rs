@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use rcgen::generate_simple_self_signed;
+use reqwest::tls::TlsInfo;
+use uuid::Uuid;
+use zero2prod::configuration::TlsSettings;
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+/// Writes a new self-signed certificate for 127.0.0.1 and returns it, DER encoded.
+fn write_certificate(directory: &Path) -> Vec<u8> {
+    let generated = generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+    std::fs::write(directory.join("cert.pem"), generated.cert.pem()).unwrap();
+    std::fs::write(
+        directory.join("key.pem"),
+        generated.key_pair.serialize_pem(),
+    )
+    .unwrap();
+    generated.cert.der().to_vec()
+}
+
+async fn spawn_https_app() -> (TestApp, PathBuf, Vec<u8>) {
+    let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir_all(&directory).unwrap();
+    let certificate = write_certificate(&directory);
+
+    let tls = TlsSettings {
+        certificate_path: directory.join("cert.pem").to_string_lossy().into_owned(),
+        private_key_path: directory.join("key.pem").to_string_lossy().into_owned(),
+        http_redirect_port: Some(0),
+    };
+    let app = spawn_app_with(|c| {
+        c.application.base_url = "https://127.0.0.1".to_string();
+        c.application.tls = Some(tls);
+    })
+    .await;
+    (app, directory, certificate)
+}
+
+/// Fetches the health check over a new connection, returning the response and
+/// the certificate the server presented.
+async fn get_health_check(app: &TestApp) -> (reqwest::Response, Vec<u8>) {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("https://127.0.0.1:{}/health_check", app.port))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let certificate = response
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(|tls_info| tls_info.peer_certificate())
+        .unwrap()
+        .to_vec();
+    (response, certificate)
+}
+
+#[tokio::test]
+async fn https_is_served_with_http2() {
+    let (app, directory, certificate) = spawn_https_app().await;
+
+    let (response, presented) = get_health_check(&app).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    assert_eq!(presented, certificate);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn plain_http_is_redirected_to_https() {
+    let (app, directory, _) = spawn_https_app().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/subscriptions/confirm?subscription_token=abc",
+            app.http_redirect_port.unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 308);
+    assert_eq!(
+        response.headers()["Location"],
+        format!(
+            "https://127.0.0.1:{}/subscriptions/confirm?subscription_token=abc",
+            app.port
+        )
+        .as_str()
+    );
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn renewed_certificates_are_served_without_a_restart() {
+    let (app, directory, _) = spawn_https_app().await;
+    let renewed = write_certificate(&directory);
+
+    app.reload_settings(|_| {}).unwrap();
+
+    let (response, presented) = get_health_check(&app).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(presented, renewed);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn a_broken_certificate_is_rejected_and_the_old_one_is_kept() {
+    let (app, directory, certificate) = spawn_https_app().await;
+    std::fs::write(directory.join("cert.pem"), "not a certificate").unwrap();
+
+    assert!(app.reload_settings(|_| {}).is_err());
+
+    let (_, presented) = get_health_check(&app).await;
+    assert_eq!(presented, certificate);
+    std::fs::remove_dir_all(directory).unwrap();
+}